@@ -0,0 +1,138 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::gamemode::GameModeKind;
+use crate::player::ComponentSpecialization;
+use crate::stats::ScoreFormula;
+
+/// A module that new ships start with, placed relative to the root in
+/// module widths and turned by rotation radians
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LoadoutModule {
+    pub module: ComponentSpecialization,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+/// What bullets and ships do to teammates
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FriendlyFire {
+    /// Teammates are damaged like anyone else
+    On,
+    /// Bullets hit teammates and their shields but do no damage
+    NoDamage,
+    /// Bullets and ships fly through teammates
+    PassThrough,
+}
+
+/// The rules of the world the server runs. Clients get a copy when they
+/// join so that they draw the same world
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GameConfig {
+    pub world_size: f32,
+    /// Server updates per second
+    pub tick_rate: u32,
+    /// Asteroids at startup, no more than max_asteroids
+    pub initial_asteroids: usize,
+    /// Seconds between new asteroids
+    pub asteroid_spawn_interval: f32,
+    /// Most asteroids there can be, fragments included
+    pub max_asteroids: usize,
+    pub max_players: usize,
+    /// Modules around the root of a new ship
+    pub starting_loadout: Vec<LoadoutModule>,
+    /// Whether ships pick up modules that broke off other ships
+    pub salvage_debris: bool,
+    /// Most modules a ship can have, including the root
+    pub max_modules: usize,
+    /// Seconds a ship has to wait between building modules
+    pub build_cooldown: f32,
+    /// Ore a new ship has to build with
+    pub starting_ore: f32,
+    /// Seconds a dead player has to wait before respawning
+    pub respawn_delay: f32,
+    /// Seconds a new ship takes no damage for, unless it shoots
+    pub spawn_protection: f32,
+    /// What the scoreboard is sorted by
+    pub score: ScoreFormula,
+    pub mode: GameModeKind,
+    /// Kills a player, or a team, needs to win a round
+    pub score_limit: u32,
+    /// Seconds a round lasts at most, 0 for no limit
+    pub round_time: f32,
+    /// Seconds of warmup before a round starts
+    pub warmup_time: f32,
+    /// Seconds the results are shown for after a round
+    pub round_end_time: f32,
+    /// Players needed for the warmup to count down
+    pub min_players: usize,
+    /// Number of teams in team modes
+    pub teams: u8,
+    pub friendly_fire: FriendlyFire,
+}
+
+impl GameConfig {
+    pub fn delta_time(&self) -> f32 {
+        1. / self.tick_rate as f32
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            // currently matches the background image size
+            world_size: 10000.,
+            tick_rate: 100,
+            initial_asteroids: 40,
+            asteroid_spawn_interval: 10.,
+            max_asteroids: 80,
+            max_players: 32,
+            starting_loadout: vec![
+                LoadoutModule {
+                    module: ComponentSpecialization::Cannon {
+                        cooldown: 0.,
+                        aim: true,
+                    },
+                    x: 0.,
+                    y: 1.,
+                    rotation: 0.,
+                },
+                LoadoutModule {
+                    module: ComponentSpecialization::Thrusters,
+                    x: 0.,
+                    y: 2.,
+                    rotation: 0.,
+                },
+                LoadoutModule {
+                    module: ComponentSpecialization::Shield,
+                    x: 1.,
+                    y: 0.,
+                    rotation: 0.,
+                },
+                LoadoutModule {
+                    module: ComponentSpecialization::Reactionwheel { angle: 0. },
+                    x: -1.,
+                    y: 0.,
+                    rotation: 0.,
+                },
+            ],
+            salvage_debris: true,
+            max_modules: 32,
+            build_cooldown: 0.5,
+            starting_ore: 100.,
+            respawn_delay: 3.,
+            spawn_protection: 3.,
+            score: ScoreFormula::default(),
+            mode: GameModeKind::FreeForAll,
+            score_limit: 20,
+            round_time: 600.,
+            warmup_time: 15.,
+            round_end_time: 10.,
+            min_players: 2,
+            teams: 2,
+            friendly_fire: FriendlyFire::NoDamage,
+        }
+    }
+}
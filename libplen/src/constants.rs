@@ -1,13 +1,107 @@
-// currently hardcoded to the background image size
-pub const WORLD_SIZE: f32 = 10000.;
-pub const DELTA_TIME: f32 = 0.01;
-pub const SERVER_SLEEP_DURATION: u64 = 10;
+// how many sent snapshots the server remembers as delta baselines
+pub const SNAPSHOT_HISTORY: usize = 64;
+
+// how far in the past the client renders other entities, in seconds
+pub const DEFAULT_INTERPOLATION_DELAY: f32 = 0.1;
+// how long the client keeps moving entities when snapshots are late
+pub const MAX_EXTRAPOLATION: f32 = 0.25;
+// seconds between pings from the client
+pub const PING_INTERVAL: f32 = 1.;
+// seconds between heartbeats in both directions
+pub const HEARTBEAT_INTERVAL: f32 = 0.5;
+// seconds of silence after which a client's input is treated as released
+pub const STALE_INPUT_TIMEOUT: f32 = 2.;
+// inputs a client can be ahead of the server before the oldest are dropped
+pub const MAX_QUEUED_INPUTS: usize = 8;
+// default seconds of silence after which a connection is considered dead
+pub const CONNECTION_TIMEOUT: f32 = 10.;
+// seconds a client may have unsent data queued before it is kicked
+pub const SLOW_CLIENT_TIMEOUT: f32 = 5.;
+// seconds a kicked client's connection stays open to deliver the reason
+pub const KICK_LINGER_TIME: f32 = 2.;
+// seconds the client waits for the server to assign it an id
+pub const CONNECT_TIMEOUT: f32 = 5.;
+// seconds between checks for the id while connecting
+pub const CONNECT_POLL_INTERVAL: f32 = 0.01;
+// seconds a dropped player's ship stays in the world waiting for a reconnect
+pub const RECONNECT_GRACE_PERIOD: f32 = 30.;
+// seconds the client waits before its first reconnect attempt, doubled after
+// every failed attempt up to the maximum
+pub const RECONNECT_DELAY: f32 = 0.5;
+pub const MAX_RECONNECT_DELAY: f32 = 10.;
 
 pub const WINDOW_SIZE: f32 = 700.;
 
 pub const NUM_STARS: i32 = 1000;
 
 pub const MODULE_RADIUS: f32 = 32.;
+// modules can only be rotated in steps of this many radians
+pub const MODULE_ROTATION_STEP: f32 = std::f32::consts::PI / 4.;
+// modules closer than this are connected to each other, including diagonally
+pub const MODULE_LINK_DISTANCE: f32 = MODULE_RADIUS * 3.;
+// speed at which modules that break off are pushed away from the ship
+pub const DEBRIS_SPEED: f32 = 100.;
+// seconds before loose modules disappear
+pub const DEBRIS_LIFETIME: f32 = 30.;
+// seconds before loose modules can be picked up, so that they don't snap
+// right back onto the ship they came from
+pub const SALVAGE_DELAY: f32 = 2.;
 pub const SHIELD_SEGMENT_RADIUS: f32 = 10.;
+// shield points added to the arc by every shield module
+pub const SHIELD_POINTS_PER_MODULE: usize = 8;
+// radius of the shield arc with a single shield module
+pub const SHIELD_BASE_RADIUS: f32 = 100.;
+// how much further out the arc is for every extra shield module
+pub const SHIELD_RADIUS_PER_MODULE: f32 = 16.;
+
+// force of a single thruster at full throttle
+pub const THRUSTER_FORCE: f32 = 1_000_000.;
+// torque of a single reaction wheel
+pub const REACTION_WHEEL_TORQUE: f32 = 5_000_000.;
+// fraction of the ship's drift and spin per second that flight assist tries
+// to cancel when there is no input
+pub const FLIGHT_ASSIST_DAMPING: f32 = 1.5;
+
+// energy needed to raise the shield again once it is down
+pub const SHIELD_MIN_ENERGY: f32 = 20.;
+
+// seconds a rejected build stays on the client's screen
+pub const BUILD_ERROR_DURATION: f32 = 2.;
+// seconds a bullet hit stays visible on the client
+pub const HIT_EFFECT_DURATION: f32 = 0.3;
 
 pub const ASTEROID_SIZE: f32 = 300.;
+// ore in a new asteroid of ASTEROID_SIZE
+pub const ASTEROID_ORE: f32 = 200.;
+// asteroids with less ore than this crumble away
+pub const ASTEROID_MIN_ORE: f32 = 5.;
+// ore that can be mined out of an asteroid before it cracks in two
+pub const ASTEROID_CRACK_ORE: f32 = 40.;
+// speed at which the halves of a cracked asteroid drift apart
+pub const ASTEROID_SPLIT_SPEED: f32 = 30.;
+pub const ASTEROID_HP_PER_ORE: f32 = 1.;
+// asteroids take damage when a collision changes their speed by more than this
+pub const ASTEROID_IMPACT_SPEED: f32 = 20.;
+// damage per unit of speed an asteroid is knocked by
+pub const ASTEROID_IMPACT_DAMAGE: f32 = 2.;
+
+// how far from the surface of an asteroid a mining laser reaches
+pub const MINING_RANGE: f32 = 150.;
+// ore per second mined by a single mining laser
+pub const MINING_RATE: f32 = 10.;
+
+// seconds a kill stays in the kill feed
+pub const KILLFEED_DURATION: f32 = 5.;
+// most kills shown in the kill feed at once
+pub const KILLFEED_LENGTH: usize = 4;
+// seconds after being damaged by a player that a death still counts as their kill
+pub const KILL_CREDIT_TIME: f32 = 10.;
+// modules take damage when hitting an asteroid changes their speed by more than this
+pub const SHIP_IMPACT_SPEED: f32 = 100.;
+// damage per unit of speed a module is knocked by when it hits an asteroid
+pub const SHIP_IMPACT_DAMAGE: f32 = 0.5;
+
+// random spots considered when picking where a ship spawns
+pub const SPAWN_CANDIDATES: usize = 20;
+// closest to the edge of the world that ships spawn
+pub const SPAWN_MARGIN: f32 = 500.;
@@ -0,0 +1,154 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::GameConfig;
+use crate::player::Player;
+
+/// The rules that decide who wins a round
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum GameModeKind {
+    /// The first player to reach the score limit in kills wins
+    FreeForAll,
+    /// Two teams, the first one to reach the score limit in kills wins
+    TeamDeathmatch,
+    /// Nobody respawns during the round, the last ship flying wins
+    Elimination,
+}
+
+impl GameModeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameModeKind::FreeForAll => "Free for all",
+            GameModeKind::TeamDeathmatch => "Team deathmatch",
+            GameModeKind::Elimination => "Elimination",
+        }
+    }
+
+    pub fn has_teams(&self) -> bool {
+        *self == GameModeKind::TeamDeathmatch
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Winner {
+    Player(u64),
+    Team(u8),
+    Draw,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Phase {
+    /// Waiting for enough players, then counting down to the round
+    Warmup,
+    Active,
+    /// Showing who won before the next warmup
+    RoundOver {
+        winner: Winner,
+    },
+}
+
+/// The round being played, sent to clients with the rest of the state
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct GameMode {
+    pub kind: GameModeKind,
+    pub phase: Phase,
+    // seconds until the phase ends, infinite if the round has no time limit
+    pub time_left: f32,
+    // number of rounds started
+    pub round: u32,
+    // kills of each team this round, kept when players leave or switch
+    pub team_scores: Vec<u32>,
+}
+
+impl GameMode {
+    pub fn new(config: &GameConfig) -> GameMode {
+        GameMode {
+            kind: config.mode,
+            phase: Phase::Warmup,
+            time_left: config.warmup_time,
+            round: 0,
+            team_scores: vec![0; config.teams as usize],
+        }
+    }
+
+    /// Dead ships stay dead until the end of an elimination round
+    pub fn allows_respawn(&self) -> bool {
+        !(self.kind == GameModeKind::Elimination && self.phase == Phase::Active)
+    }
+
+    pub fn team_name(team: u8) -> String {
+        match team {
+            0 => "Red".to_string(),
+            1 => "Blue".to_string(),
+            2 => "Green".to_string(),
+            3 => "Yellow".to_string(),
+            _ => format!("Team {}", team + 1),
+        }
+    }
+
+    /// Kills made by the team's players this round
+    pub fn team_score(&self, team: u8) -> u32 {
+        self.team_scores.get(team as usize).copied().unwrap_or(0)
+    }
+
+    pub fn add_team_kill(&mut self, team: u8) {
+        if let Some(score) = self.team_scores.get_mut(team as usize) {
+            *score += 1;
+        }
+    }
+
+    /// The team with the fewest players, which new players join
+    pub fn smallest_team(players: &[Player], teams: u8) -> u8 {
+        (0..teams)
+            .min_by_key(|team| players.iter().filter(|p| p.team == Some(*team)).count())
+            .unwrap_or(0)
+    }
+
+    /// How the round ended, if the win condition of the mode has been met
+    pub fn winner(&self, players: &[Player], config: &GameConfig) -> Option<Winner> {
+        match self.kind {
+            GameModeKind::FreeForAll => players
+                .iter()
+                .find(|player| player.stats.kills >= config.score_limit)
+                .map(|player| Winner::Player(player.id)),
+            GameModeKind::TeamDeathmatch => (0..config.teams)
+                .find(|team| self.team_score(*team) >= config.score_limit)
+                .map(Winner::Team),
+            GameModeKind::Elimination => {
+                let alive = players
+                    .iter()
+                    .filter(|player| !player.requesting_death)
+                    .collect::<Vec<_>>();
+                match alive[..] {
+                    [] => Some(Winner::Draw),
+                    [last] => Some(Winner::Player(last.id)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Who is ahead when the time runs out
+    pub fn leader(&self, players: &[Player], config: &GameConfig) -> Winner {
+        let scores = if self.kind.has_teams() {
+            (0..config.teams)
+                .map(|team| (Winner::Team(team), self.team_score(team)))
+                .collect::<Vec<_>>()
+        } else {
+            players
+                .iter()
+                .filter(|player| self.kind != GameModeKind::Elimination || !player.requesting_death)
+                .map(|player| (Winner::Player(player.id), player.stats.kills))
+                .collect::<Vec<_>>()
+        };
+
+        let best = scores.iter().map(|(_, score)| *score).max();
+        let leaders = scores
+            .iter()
+            .filter(|(_, score)| Some(*score) == best)
+            .collect::<Vec<_>>();
+        match leaders[..] {
+            [(winner, _)] => *winner,
+            _ => Winner::Draw,
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use macroquad::rand::gen_range;
 use rapier2d::prelude::*;
 use serde_derive::{Deserialize, Serialize};
+use crate::config::{FriendlyFire, GameConfig};
 use crate::physics::PhysicsState;
 use crate::constants;
 
-use crate::{math::Vec2, player::Player};
+use crate::gamemode::{GameMode, Phase};
+use crate::killfeed::{Kill, KillCause, KillFeed};
+use crate::power::PowerSystem;
+use crate::stats::Stats;
+use crate::{math::{vec2, Vec2}, player::{Component, ComponentSpecialization, Player}};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GameState {
@@ -12,15 +17,51 @@ pub struct GameState {
     pub bullets: Vec<Bullet>,
     pub asteroids: Vec<Asteroid>,
     pub asteroid_timer: i32,
+    // increases by one every server update
+    pub tick: u64,
+    // seconds since the server was started
+    pub server_time: f64,
+    // bullet hits during the last update, for the clients to draw
+    pub hits: Vec<Hit>,
+    // modules that broke off their ships
+    pub debris: Vec<Debris>,
+    pub killfeed: KillFeed,
+    pub mode: GameMode,
     // put server side game state stuff here
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Bullet {
     pub handle: RigidBodyHandle,
+    // id of the player that fired it
+    pub owner: u64,
+    pub damage: f32,
     pub lifetime: f32,
     pub pos: Vec2,
     pub angle: f32,
+    pub linvel: Vec2,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum HitTarget {
+    Component { player: u64, destroyed: bool },
+    Shield { player: u64 },
+    Asteroid { destroyed: bool },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hit {
+    pub pos: Vec2,
+    // id of the player that fired the bullet
+    pub shooter: u64,
+    pub target: HitTarget,
+    pub damage: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Debris {
+    pub component: Component,
+    pub age: f32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -28,61 +69,99 @@ pub struct Asteroid {
     pub handle: RigidBodyHandle,
     pub x: f32,
     pub y: f32,
-    pub angle: f32
+    pub angle: f32,
+    pub linvel: Vec2,
+    pub angvel: f32,
+    pub ore: f32,
+    // diameter, follows the ore
+    pub size: f32,
+    // ore taken out since the asteroid last cracked
+    pub mined: f32,
+    pub hp: f32,
 }
 
-const NUM_ASTEROIDS: usize = 40;
+// mass of an asteroid holding ASTEROID_ORE
 const ASTEROID_MASS: f32 = 20000.;
 
 impl Asteroid {
-    pub fn new(p: &mut PhysicsState) -> Asteroid {
-        let x = gen_range(0, constants::WORLD_SIZE as i32) as f32;
-        let y = gen_range(0, constants::WORLD_SIZE as i32) as f32;
-
-        let vx = gen_range(0, 1000) as f32 / 100.0;
-        let vy = gen_range(0, 1000) as f32 / 100.0;
+    pub fn new(p: &mut PhysicsState, world_size: f32) -> Asteroid {
+        let x = gen_range(0, world_size as i32) as f32;
+        let y = gen_range(0, world_size as i32) as f32;
 
         let pos = Vec2 {x, y};
 
+        Asteroid::with_ore(p, pos, vec2(0., 0.), constants::ASTEROID_ORE)
+    }
+
+    /// An asteroid sized to the ore it holds
+    pub fn with_ore(p: &mut PhysicsState, pos: Vec2, linvel: Vec2, ore: f32) -> Asteroid {
         let rb = RigidBodyBuilder::dynamic()
-            .translation(vector![x, y])
+            .translation(vector![pos.x, pos.y])
+            .linvel(vector![linvel.x, linvel.y])
             .build();
+        let body_handle = p.rigid_body_set.insert(rb);
+
+        let mut asteroid = Asteroid {
+            handle: body_handle,
+            x: pos.x,
+            y: pos.y,
+            angle: 0.,
+            linvel,
+            angvel: 0.,
+            ore: 0.,
+            size: 0.,
+            mined: 0.,
+            hp: ore * constants::ASTEROID_HP_PER_ORE,
+        };
+        asteroid.set_ore(ore, p);
+        asteroid
+    }
+
+    /// Changes the ore and shrinks the asteroid to match
+    pub fn set_ore(&mut self, ore: f32, p: &mut PhysicsState) {
+        self.ore = ore;
+        self.size = constants::ASTEROID_SIZE * (ore / constants::ASTEROID_ORE).sqrt();
 
-        let collider = ColliderBuilder::ball(constants::ASTEROID_SIZE/2.)
+        let colliders = p.rigid_body_set.get(self.handle).unwrap().colliders().to_vec();
+        for collider in colliders {
+            p.collider_set.remove(collider, &mut p.island_manager, &mut p.rigid_body_set, true);
+        }
+
+        let collider = ColliderBuilder::ball(self.size / 2.)
             .restitution(0.2)
             .friction(0.5)
-            .mass(ASTEROID_MASS)
+            .mass(ASTEROID_MASS * ore / constants::ASTEROID_ORE)
             .build();
-
-        let body_handle = p.rigid_body_set.insert(rb);
         p.collider_set
-            .insert_with_parent(collider, body_handle, &mut p.rigid_body_set);
+            .insert_with_parent(collider, self.handle, &mut p.rigid_body_set);
+    }
 
-        Asteroid {
-            handle: body_handle,
-            x: x,
-            y: y,
-            angle: 0.,
-        }
+    pub fn destroy_physics(&self, p: &mut PhysicsState) {
+        p.rigid_body_set.remove(self.handle, &mut p.island_manager, &mut p.collider_set, &mut p.impulse_joint_set, &mut p.multibody_joint_set, true);
     }
 }
 
 impl Bullet {
 
-    pub fn collides_with(self, x: f32, y: f32, radius: f32) -> bool {
+    pub fn collides_with(&self, x: f32, y: f32, radius: f32) -> bool {
         ((self.pos.x - x).powi(2) + (self.pos.y - y).powi(2)).sqrt() < radius
     }
 
+    pub fn destroy_physics(&self, p: &mut PhysicsState) {
+        p.rigid_body_set.remove(self.handle, &mut p.island_manager, &mut p.collider_set, &mut p.impulse_joint_set, &mut p.multibody_joint_set, true);
+    }
+
 }
 
 impl GameState {
-    pub fn new(op: Option<&mut PhysicsState>) -> GameState {
+    pub fn new(op: Option<&mut PhysicsState>, config: &GameConfig) -> GameState {
         let mut asteroids = vec![];
         match op {
             None => {},
             Some(p) => {
-                for _ in 0..NUM_ASTEROIDS {
-                    asteroids.push(Asteroid::new(p));
+                // The limit holds for the asteroids at startup too
+                for _ in 0..config.initial_asteroids.min(config.max_asteroids) {
+                    asteroids.push(Asteroid::new(p, config.world_size));
                 }
             }
         }
@@ -91,6 +170,12 @@ impl GameState {
             bullets: Vec::new(),
             asteroids: asteroids,
             asteroid_timer: 0,
+            tick: 0,
+            server_time: 0.,
+            hits: Vec::new(),
+            debris: Vec::new(),
+            killfeed: KillFeed::new(),
+            mode: GameMode::new(config),
             // init server side game state stuff here
         }
     }
@@ -103,24 +188,105 @@ impl GameState {
      *  vec with positions where lasers are fired
      *  )
      */
-    pub fn update(&mut self, delta: f32, p: &mut PhysicsState) {
-        if self.asteroid_timer == 0 {
-            self.asteroid_timer = 1000;
-            self.asteroids.push(Asteroid::new(p));
+    pub fn update(&mut self, delta: f32, p: &mut PhysicsState, config: &GameConfig) {
+        self.tick += 1;
+
+        if self.asteroid_timer <= 0 && self.asteroids.len() < config.max_asteroids {
+            self.asteroid_timer = (config.asteroid_spawn_interval * config.tick_rate as f32) as i32;
+            self.asteroids.push(Asteroid::new(p, config.world_size));
             println!("NEW ASTEROID!");
         }
         self.asteroid_timer -= 1;
 
+        self.update_mode(delta, p, config);
+
+        for i in 0..self.players.len() {
+            let player = &self.players[i];
+            let can_respawn = player.respawn_timer <= 0. && self.mode.allows_respawn();
+            if player.requesting_death && can_respawn && player.input_x > 0. {
+                let spawn = self.spawn_point(config);
+                self.players[i].reset(p, config, spawn);
+            }
+        }
+
+        let already_dead = self
+            .players
+            .iter()
+            .filter(|player| player.requesting_death)
+            .map(|player| player.id)
+            .collect::<Vec<_>>();
+
+        for player in &mut self.players {
+            player.update(delta, &mut self.bullets, p, config);
+        }
+        self.asteroid_impacts(p);
+
+        self.hits.clear();
+        for bullet in std::mem::take(&mut self.bullets) {
+            match self.bullet_hit(&bullet, config) {
+                Some(hit) => {
+                    bullet.destroy_physics(p);
+                    self.hits.push(hit);
+                }
+                None => self.bullets.push(bullet),
+            }
+        }
+        for hit in self.hits.clone() {
+            if let Some(shooter) = self.get_player_by_id_mut(hit.shooter) {
+                match hit.target {
+                    HitTarget::Component { .. } => shooter.stats.damage_dealt += hit.damage,
+                    HitTarget::Asteroid { destroyed: true } => {
+                        shooter.stats.asteroids_destroyed += 1
+                    }
+                    _ => {}
+                }
+            }
+        }
         for player in &mut self.players {
-            player.update(delta, &mut self.bullets, p);
+            for component in player.remove_destroyed_components(p) {
+                self.debris.push(Debris { component, age: 0. });
+            }
         }
+        let kills = self
+            .players
+            .iter()
+            .filter(|player| player.requesting_death && !already_dead.contains(&player.id))
+            .map(|player| Kill {
+                killer: player.killer(),
+                victim: player.id,
+                cause: player.death_cause.unwrap_or(KillCause::OutOfBounds),
+            })
+            .collect::<Vec<_>>();
+        for kill in kills {
+            println!("Player {} died: {:?}, killer {:?}", kill.victim, kill.cause, kill.killer);
+            let mut victim_team = None;
+            if let Some(victim) = self.get_player_by_id_mut(kill.victim) {
+                victim.stats.deaths += 1;
+                victim.respawn_timer = config.respawn_delay;
+                victim_team = victim.team;
+            }
+            if let Some(killer) = kill.killer.and_then(|id| self.get_player_by_id_mut(id)) {
+                // Team kills don't count
+                if killer.team.is_none() || killer.team != victim_team {
+                    killer.stats.kills += 1;
+                    if let Some(team) = killer.team {
+                        self.mode.add_team_kill(team);
+                    }
+                }
+            }
+            self.killfeed.add_kill(kill, self.server_time);
+        }
+        self.killfeed.manage_killfeed(self.server_time);
+        self.update_debris(delta, p, config);
+        self.mine(delta, p, config);
+        self.damage_asteroids(p, config);
 
         let mut i: usize = 0;
         while i < self.bullets.len() {
             let bullet = &mut self.bullets[i];
 
             if bullet.lifetime > 3. {
-                self.bullets.remove(i);
+                self.bullets.remove(i).destroy_physics(p);
             } else {
                 bullet.lifetime += delta;
                 i += 1;
@@ -133,11 +299,452 @@ impl GameState {
             asteroid.x = pos.translation.x;
             asteroid.y = pos.translation.y;
             asteroid.angle = angle;
+            asteroid.linvel = vec2(rb.linvel().x, rb.linvel().y);
+            asteroid.angvel = rb.angvel();
+        }
+    }
+
+    /// Moves the round along: warmup, the round itself, and the results
+    fn update_mode(&mut self, delta: f32, p: &mut PhysicsState, config: &GameConfig) {
+        self.mode.time_left -= delta;
+        match self.mode.phase {
+            Phase::Warmup => {
+                if self.players.len() < config.min_players {
+                    self.mode.time_left = config.warmup_time;
+                } else if self.mode.time_left <= 0. {
+                    self.start_round(p, config);
+                }
+            }
+            Phase::Active => {
+                let winner = match self.mode.winner(&self.players, config) {
+                    Some(winner) => Some(winner),
+                    None if self.mode.time_left <= 0. => {
+                        Some(self.mode.leader(&self.players, config))
+                    }
+                    None => None,
+                };
+                if let Some(winner) = winner {
+                    println!("Round {} over, winner: {:?}", self.mode.round, winner);
+                    self.mode.phase = Phase::RoundOver { winner };
+                    self.mode.time_left = config.round_end_time;
+                }
+            }
+            Phase::RoundOver { .. } => {
+                if self.mode.time_left <= 0. {
+                    self.mode.phase = Phase::Warmup;
+                    self.mode.time_left = config.warmup_time;
+                }
+            }
+        }
+    }
+
+    /// Clears the scores, picks the teams and gives everyone a new ship
+    fn start_round(&mut self, p: &mut PhysicsState, config: &GameConfig) {
+        self.mode.round += 1;
+        self.mode.phase = Phase::Active;
+        self.mode.time_left = if config.round_time > 0. {
+            config.round_time
+        } else {
+            f32::INFINITY
+        };
+        self.mode.team_scores = vec![0; config.teams as usize];
+        println!("Round {} of {} started", self.mode.round, self.mode.kind.name());
+
+        for i in 0..self.players.len() {
+            let spawn = self.spawn_point(config);
+            let player = &mut self.players[i];
+            player.stats = Stats::new();
+            player.team = if self.mode.kind.has_teams() {
+                Some((i % config.teams.max(1) as usize) as u8)
+            } else {
+                None
+            };
+            player.reset(p, config, spawn);
+        }
+    }
+
+    /// Moves players from the biggest team to the smallest until no team has
+    /// more than one player more than another. Dead players are moved first,
+    /// then the ones with the fewest kills. Living ones get a new ship like
+    /// when switching teams
+    fn balance_teams(&mut self, p: &mut PhysicsState, config: &GameConfig) {
+        loop {
+            let size = |team: u8| self.players.iter().filter(|p| p.team == Some(team)).count();
+            let biggest = (0..config.teams).max_by_key(|team| size(*team));
+            let smallest = (0..config.teams).min_by_key(|team| size(*team));
+            let (biggest, smallest) = match (biggest, smallest) {
+                (Some(biggest), Some(smallest)) if size(biggest) > size(smallest) + 1 => {
+                    (biggest, smallest)
+                }
+                _ => return,
+            };
+
+            let spawn = self.spawn_point(config);
+            let can_respawn = self.mode.allows_respawn();
+            let player = self
+                .players
+                .iter_mut()
+                .filter(|p| p.team == Some(biggest))
+                .min_by_key(|p| (!p.requesting_death, p.stats.kills))
+                .unwrap();
+            println!(
+                "Moved player {} to team {} to balance the teams",
+                player.id,
+                GameMode::team_name(smallest)
+            );
+            player.team = Some(smallest);
+            if can_respawn && !player.requesting_death {
+                player.reset(p, config, spawn);
+            }
+        }
+    }
+
+    /// Puts the player on another team with a new ship. Players can only
+    /// switch to teams that are smaller than their own
+    pub fn switch_team(
+        &mut self,
+        id: u64,
+        team: u8,
+        p: &mut PhysicsState,
+        config: &GameConfig,
+    ) -> Result<(), String> {
+        if !self.mode.kind.has_teams() {
+            return Err("the mode has no teams".to_string());
+        }
+        if team >= config.teams {
+            return Err(format!("there is no team {}", team));
+        }
+        let current = match self.get_player_by_id(id) {
+            Some(player) => player.team,
+            None => return Err("the player has no ship".to_string()),
+        };
+        let size = |team: Option<u8>| self.players.iter().filter(|p| p.team == team).count();
+        if current == Some(team) || size(Some(team)) >= size(current) {
+            return Err(format!("{} team is not smaller", GameMode::team_name(team)));
+        }
+
+        let spawn = self.spawn_point(config);
+        let can_respawn = self.mode.allows_respawn();
+        let player = self.get_player_by_id_mut(id).unwrap();
+        player.team = Some(team);
+        if can_respawn && !player.requesting_death {
+            player.reset(p, config, spawn);
+        }
+        Ok(())
+    }
+
+    /// Mining lasers take ore out of the closest asteroid in range. Asteroids
+    /// crack in two as they are mined and crumble away once they run out
+    fn mine(&mut self, delta: f32, p: &mut PhysicsState, config: &GameConfig) {
+        for player in &mut self.players {
+            player.mining_beams = vec![];
+            if !player.shoot
+                || player.requesting_death
+                || !player.power.is_powered(PowerSystem::Weapons)
+            {
+                continue;
+            }
+
+            let lasers = player
+                .components
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.spec == ComponentSpecialization::MiningLaser)
+                .map(|(i, c)| (i, c.pos))
+                .collect::<Vec<_>>();
+            for (index, pos) in lasers {
+                let target = self
+                    .asteroids
+                    .iter_mut()
+                    .map(|a| ((vec2(a.x, a.y) - pos).norm() - a.size / 2., a))
+                    .filter(|(distance, _)| *distance < constants::MINING_RANGE)
+                    .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+                    .map(|(_, asteroid)| asteroid);
+
+                if let Some(asteroid) = target {
+                    let amount = (constants::MINING_RATE * delta).min(asteroid.ore);
+                    asteroid.set_ore(asteroid.ore - amount, p);
+                    asteroid.mined += amount;
+                    player.ore += amount;
+                    player.mining_beams.push((index, asteroid.handle));
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < self.asteroids.len() {
+            if self.asteroids[i].ore < constants::ASTEROID_MIN_ORE {
+                self.asteroids.remove(i).destroy_physics(p);
+            } else if self.asteroids[i].mined >= constants::ASTEROID_CRACK_ORE
+                && self.asteroids.len() < config.max_asteroids
+            {
+                // The halves go to the end of the list
+                self.split_asteroid(i, p);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Modules that ram into asteroids, or get rammed by them, take damage by
+    /// how much the hit changed the speed of their ship during the last
+    /// physics step
+    fn asteroid_impacts(&mut self, p: &PhysicsState) {
+        for asteroid in &self.asteroids {
+            let colliders = p.rigid_body_set.get(asteroid.handle).unwrap().colliders();
+            for collider in colliders {
+                for pair in p.narrow_phase.contacts_with(*collider) {
+                    if !pair.has_any_active_contact {
+                        continue;
+                    }
+                    let other = if pair.collider1 == *collider {
+                        pair.collider2
+                    } else {
+                        pair.collider1
+                    };
+                    let body = match p.collider_set.get(other).and_then(|c| c.parent()) {
+                        Some(body) => body,
+                        None => continue,
+                    };
+
+                    for player in &mut self.players {
+                        let index = player.components.iter().position(|c| c.physics_handle == body);
+                        let index = match index {
+                            Some(index) => index,
+                            None => continue,
+                        };
+
+                        // The modules are light but held by the rest of the
+                        // ship, so the impulse moves all of it
+                        let mass = player
+                            .components
+                            .iter()
+                            .filter_map(|c| p.rigid_body_set.get(c.physics_handle))
+                            .map(|rb| rb.mass())
+                            .sum::<f32>();
+                        let impact = pair.total_impulse_magnitude() / mass;
+                        if impact >= constants::SHIP_IMPACT_SPEED {
+                            player.damage_component(
+                                index,
+                                impact * constants::SHIP_IMPACT_DAMAGE,
+                                KillCause::Asteroid,
+                                None,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Collisions damage asteroids by how much they changed their speed during
+    /// the last physics step. Destroyed asteroids split in two, or break up
+    /// completely if they are small or there are too many asteroids
+    fn damage_asteroids(&mut self, p: &mut PhysicsState, config: &GameConfig) {
+        for asteroid in &mut self.asteroids {
+            let rb = p.rigid_body_set.get(asteroid.handle).unwrap();
+            let impact = (vec2(rb.linvel().x, rb.linvel().y) - asteroid.linvel).norm();
+            if impact > constants::ASTEROID_IMPACT_SPEED {
+                asteroid.hp -= impact * constants::ASTEROID_IMPACT_DAMAGE;
+            }
+        }
+
+        let mut i = 0;
+        while i < self.asteroids.len() {
+            let asteroid = &self.asteroids[i];
+            if asteroid.hp > 0. {
+                i += 1;
+            } else if asteroid.ore / 2. < constants::ASTEROID_MIN_ORE
+                || self.asteroids.len() >= config.max_asteroids
+            {
+                self.asteroids.remove(i).destroy_physics(p);
+            } else {
+                self.split_asteroid(i, p);
+            }
+        }
+    }
+
+    /// Breaks the asteroid into two halves that drift apart and keep its
+    /// momentum
+    fn split_asteroid(&mut self, index: usize, p: &mut PhysicsState) {
+        let asteroid = self.asteroids.remove(index);
+        asteroid.destroy_physics(p);
+
+        let pos = vec2(asteroid.x, asteroid.y);
+        let apart = Vec2::from_direction(gen_range(0., 2. * std::f32::consts::PI), 1.);
+        for side in [-1., 1.] {
+            self.asteroids.push(Asteroid::with_ore(
+                p,
+                pos + apart * (side * asteroid.size / 2.),
+                asteroid.linvel + apart * (side * constants::ASTEROID_SPLIT_SPEED),
+                asteroid.ore / 2.,
+            ));
         }
     }
 
-    pub fn add_player(&mut self, player: Player) {
-        self.players.push(player.clone());
+    fn update_debris(&mut self, delta: f32, p: &mut PhysicsState, config: &GameConfig) {
+        for debris in std::mem::take(&mut self.debris) {
+            let component = &debris.component;
+            if debris.age > constants::DEBRIS_LIFETIME {
+                component.clone().destroy_physics(p);
+                continue;
+            }
+
+            let salvaged = config.salvage_debris
+                && debris.age > constants::SALVAGE_DELAY
+                && self
+                    .players
+                    .iter_mut()
+                    .filter(|player| !player.requesting_death)
+                    .find(|player| {
+                        player.components.iter().any(|c| {
+                            (c.pos - component.pos).norm() < constants::MODULE_LINK_DISTANCE
+                        })
+                    })
+                    .map(|player| player.salvage(component, p, config))
+                    .unwrap_or(false);
+            if salvaged {
+                component.clone().destroy_physics(p);
+                continue;
+            }
+
+            let mut debris = debris;
+            let rb = p.rigid_body_set.get(debris.component.physics_handle).unwrap();
+            debris.component.pos = vec2(rb.translation().x, rb.translation().y);
+            debris.component.angle = rb.rotation().angle();
+            debris.component.linvel = vec2(rb.linvel().x, rb.linvel().y);
+            debris.component.angvel = rb.angvel();
+            debris.age += delta;
+            self.debris.push(debris);
+        }
+    }
+
+    /// Finds what the bullet hit and damages it. Ships can't hit themselves
+    fn bullet_hit(&mut self, bullet: &Bullet, config: &GameConfig) -> Option<Hit> {
+        let shooter_team = self.get_player_by_id(bullet.owner).and_then(|p| p.team);
+        for player in &mut self.players {
+            if player.id == bullet.owner || player.requesting_death {
+                continue;
+            }
+            let friendly = shooter_team.is_some() && player.team == shooter_team;
+            if friendly && config.friendly_fire == FriendlyFire::PassThrough {
+                continue;
+            }
+            let harmless = friendly && config.friendly_fire == FriendlyFire::NoDamage;
+
+            let blocked = player.shield.active
+                && player.shield.points.iter().any(|point| {
+                    bullet.collides_with(point.x, point.y, constants::SHIELD_SEGMENT_RADIUS)
+                });
+            if blocked {
+                if !harmless {
+                    player.power.drain(bullet.damage);
+                }
+                return Some(Hit {
+                    pos: bullet.pos,
+                    shooter: bullet.owner,
+                    target: HitTarget::Shield { player: player.id },
+                    damage: 0.,
+                });
+            }
+
+            let index = player.components.iter().position(|c| {
+                bullet.collides_with(c.pos.x, c.pos.y, constants::MODULE_RADIUS)
+            });
+            if let Some(index) = index {
+                let damage = bullet.damage * (1. - player.components[index].spec.armor());
+                let damage = if harmless {
+                    0.
+                } else {
+                    player.damage_component(index, damage, KillCause::Bullet, Some(bullet.owner))
+                };
+                return Some(Hit {
+                    pos: bullet.pos,
+                    shooter: bullet.owner,
+                    target: HitTarget::Component {
+                        player: player.id,
+                        destroyed: player.components[index].hp <= 0.,
+                    },
+                    damage,
+                });
+            }
+        }
+
+        for asteroid in &mut self.asteroids {
+            if bullet.collides_with(asteroid.x, asteroid.y, asteroid.size / 2.) {
+                let was_intact = asteroid.hp > 0.;
+                asteroid.hp -= bullet.damage;
+                return Some(Hit {
+                    pos: bullet.pos,
+                    shooter: bullet.owner,
+                    target: HitTarget::Asteroid {
+                        destroyed: was_intact && asteroid.hp <= 0.,
+                    },
+                    damage: bullet.damage,
+                });
+            }
+        }
+        None
+    }
+
+    /// Picks the spot furthest from the living ships and the asteroids out of a
+    /// few random ones
+    pub fn spawn_point(&self, config: &GameConfig) -> Vec2 {
+        let margin = constants::SPAWN_MARGIN.min(config.world_size / 2.);
+        let clearance = |pos: Vec2| {
+            let ships = self
+                .players
+                .iter()
+                .filter(|player| !player.requesting_death)
+                .map(|player| (player.position() - pos).norm());
+            let asteroids = self
+                .asteroids
+                .iter()
+                .map(|asteroid| (vec2(asteroid.x, asteroid.y) - pos).norm() - asteroid.size / 2.);
+            ships.chain(asteroids).fold(f32::INFINITY, f32::min)
+        };
+
+        (0..constants::SPAWN_CANDIDATES)
+            .map(|_| {
+                vec2(
+                    gen_range(margin, config.world_size - margin),
+                    gen_range(margin, config.world_size - margin),
+                )
+            })
+            .map(|pos| (clearance(pos), pos))
+            .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, pos)| pos)
+            .unwrap()
+    }
+
+    /// Puts the player on a team if the mode has them and gives it a ship.
+    /// Players joining an elimination round wait for the next one without one
+    pub fn add_player(&mut self, mut player: Player, p: &mut PhysicsState, config: &GameConfig) {
+        if self.mode.kind.has_teams() {
+            player.team = Some(GameMode::smallest_team(&self.players, config.teams));
+        }
+        if self.mode.allows_respawn() {
+            let spawn = self.spawn_point(config);
+            player.reset(p, config, spawn);
+        } else {
+            player.requesting_death = true;
+        }
+        self.players.push(player);
+    }
+
+    /// Takes the players and their ships out of the game. Teams are evened
+    /// out again if that left them uneven
+    pub fn remove_players(&mut self, ids: &[u64], p: &mut PhysicsState, config: &GameConfig) {
+        for player in &mut self.players {
+            if ids.contains(&player.id) {
+                player.destroy_physics(p);
+            }
+        }
+        self.players.retain(|player| !ids.contains(&player.id));
+
+        if self.mode.kind.has_teams() {
+            self.balance_teams(p, config);
+        }
     }
 
     pub fn get_player_by_id(&self, id: u64) -> Option<&Player> {
@@ -148,4 +755,8 @@ impl GameState {
         }
         None
     }
+
+    pub fn get_player_by_id_mut(&mut self, id: u64) -> Option<&mut Player> {
+        self.players.iter_mut().find(|player| player.id == id)
+    }
 }
@@ -2,19 +2,40 @@ use serde_derive::{Serialize, Deserialize};
 
 use crate::constants;
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum KillCause {
+    Bullet,
+    Asteroid,
+    OutOfBounds,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Kill {
+    // the last player to damage the victim, if it was recently enough
+    pub killer: Option<u64>,
+    pub victim: u64,
+    pub cause: KillCause,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Message {
-    pub message: String,
-    pub duration_left: Option<f32>,
+    pub kill: Kill,
+    // server time of the kill
+    pub time: f64,
 }
 
 impl Message {
-    pub fn new(message: String) -> Self {
+    pub fn new(kill: Kill, time: f64) -> Self {
         Message {
-            message,
-            duration_left: Some(constants::KILLFEED_DURATION),
+            kill,
+            time,
         }
     }
+
+    // How far through its time on screen the message is, from 0 to 1
+    pub fn age(&self, server_time: f64) -> f32 {
+        ((server_time - self.time) as f32 / constants::KILLFEED_DURATION).min(1.)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -29,33 +50,21 @@ impl KillFeed {
         }
     }
 
-    pub fn manage_killfeed(&mut self, delta: f32) {
-        // Decrease the messages time left
-        self.messages.iter_mut()
-            .for_each(|m| {
-                // Decrease the time left
-                m.duration_left = m.duration_left
-                    .map(|left| left - delta)
-            });
-
+    pub fn manage_killfeed(&mut self, server_time: f64) {
         // Remove if the time is up
         self.messages
             .retain(|m| {
-                m.duration_left
-                    .map(|left| left > 0.)
-                    .unwrap_or(true)
+                m.age(server_time) < 1.
             });
     }
 
-    pub fn add_message(&mut self, message: &str) {
-        self.messages.push(Message::new(message.to_string()));
+    pub fn add_kill(&mut self, kill: Kill, time: f64) {
+        self.messages.push(Message::new(kill, time));
     }
 
-    pub fn get_messages(&mut self) -> Vec<Message> {
-        if self.messages.len() >= 5 {
-            self.messages[..4].to_vec()
-        } else {
-            self.messages[..].to_vec()
-        }
+    // The newest messages, oldest first
+    pub fn get_messages(&self) -> Vec<Message> {
+        let start = self.messages.len().saturating_sub(constants::KILLFEED_LENGTH);
+        self.messages[start..].to_vec()
     }
 }
@@ -1,7 +1,13 @@
 pub mod player;
 pub mod constants;
+pub mod config;
 pub mod math;
+pub mod gamemode;
 pub mod gamestate;
+pub mod killfeed;
 pub mod messages;
 pub mod debug;
 pub mod physics;
+pub mod power;
+pub mod stats;
+pub mod udp;
@@ -1,7 +1,7 @@
 use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub};
 use serde_derive::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub struct Vec2 {
     pub x: f32,
     pub y: f32,
@@ -132,3 +132,8 @@ pub fn angle_diff(source_angle: f32, target_angle: f32) -> f32 {
     use std::f32::consts::PI;
     modulo(target_angle - source_angle + PI, 2. * PI) - PI
 }
+
+/// Rounds the angle to the nearest multiple of step, in the range -PI..PI
+pub fn snap_angle(angle: f32, step: f32) -> f32 {
+    angle_diff(0., (angle / step).round() * step)
+}
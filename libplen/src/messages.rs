@@ -1,16 +1,254 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::io::{self, prelude::*};
 use std::iter::Iterator;
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 
+use rapier2d::prelude::RigidBodyHandle;
 use serde_derive::{Deserialize, Serialize};
 
-use crate::math::{self, Vec2};
-use crate::player::ComponentSpecialization;
+use crate::config::GameConfig;
+use crate::gamemode::GameMode;
+use crate::gamestate::{Asteroid, Bullet, Debris, GameState, Hit};
+use crate::killfeed::KillFeed;
+use crate::math::{self, vec2, Vec2};
+use crate::player::{BuildRejection, Component, ComponentSpecialization, Player, Shield};
+use crate::power::Power;
+
+/// Bumped whenever the wire format changes so that mismatched clients and
+/// servers fail cleanly instead of misinterpreting each other
+pub const PROTOCOL_VERSION: u32 = 2;
+const PROTOCOL_MAGIC: [u8; 4] = *b"spes";
+const HANDSHAKE_SIZE: usize = 8;
+// Frames start with their length as a big endian u32
+const LENGTH_SIZE: usize = 4;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_QUEUED_BYTES: usize = 4 * 1024 * 1024;
+
+/// The bytes both sides send first on a new connection
+pub fn handshake() -> [u8; HANDSHAKE_SIZE] {
+    let mut handshake = [0; HANDSHAKE_SIZE];
+    handshake[..4].copy_from_slice(&PROTOCOL_MAGIC);
+    handshake[4..].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    handshake
+}
+
+pub(crate) fn frame_too_large(length: usize, max_frame_size: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "frame of {} bytes exceeds the maximum of {} bytes",
+            length, max_frame_size
+        ),
+    )
+}
+
+/// The error sends fail with when the peer is too far behind to queue any
+/// more for it
+#[derive(Debug)]
+pub struct QueueFull {
+    pub limit: usize,
+}
+
+impl QueueFull {
+    pub fn is(error: &io::Error) -> bool {
+        error.get_ref().map_or(false, |e| e.is::<QueueFull>())
+    }
+}
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "send queue is over its limit of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// Prefixes an encoded message with its length
+pub fn encode_frame(data: &[u8], max_frame_size: usize) -> io::Result<Vec<u8>> {
+    if data.len() > max_frame_size || data.len() > u32::MAX as usize {
+        return Err(frame_too_large(data.len(), max_frame_size));
+    }
+
+    let mut frame = Vec::with_capacity(LENGTH_SIZE + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(data);
+    Ok(frame)
+}
+
+/// Which delivery guarantees a message needs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    /// Delivered exactly once and in order
+    Reliable,
+    /// May be lost, and anything older than the newest message received on
+    /// the channel is dropped
+    Unreliable,
+}
+
+/// A connection to a single peer that messages can be sent over
+pub trait Transport {
+    /// Sends an encoded message, or queues it if it can not be sent yet
+    fn send(&mut self, channel: Channel, message: &[u8]) -> io::Result<()>;
+
+    /// Does any pending network work and returns the messages that have
+    /// arrived since the last call
+    fn receive(&mut self) -> io::Result<Vec<Vec<u8>>>;
+
+    /// Bytes that have been sent but are not on their way to the peer yet,
+    /// or have not been acknowledged by it
+    fn backlog(&self) -> usize;
+
+    /// Drops queued messages that were allowed to be lost anyway, so that
+    /// whatever is sent next goes out right away
+    fn discard_unreliable(&mut self);
+
+    /// Stops sending and lets the peer know the connection is closing.
+    /// Anything still in the backlog is lost, so wait for it to drain first
+    fn shutdown(&mut self) -> io::Result<()>;
+}
+
+/// Frames waiting for the socket to accept them. Unreliable frames that have
+/// not started going out are replaced by newer ones, so a client that falls
+/// behind gets the latest state instead of a growing pile of old ones
+struct SendQueue {
+    frames: VecDeque<(Channel, Vec<u8>)>,
+    // Bytes of the front frame that have already been written
+    written: usize,
+    queued_bytes: usize,
+    max_queued_bytes: usize,
+}
+
+impl SendQueue {
+    fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            written: 0,
+            queued_bytes: 0,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+        }
+    }
+
+    /// Removes the unreliable frames that have not started going out
+    fn discard_unreliable(&mut self) {
+        let written = self.written;
+        let mut index = 0;
+        let queued_bytes = &mut self.queued_bytes;
+        self.frames.retain(|(channel, frame)| {
+            let in_progress = index == 0 && written > 0;
+            index += 1;
+            let discarded = *channel == Channel::Unreliable && !in_progress;
+            if discarded {
+                *queued_bytes -= frame.len();
+            }
+            !discarded
+        });
+    }
+
+    fn push(&mut self, channel: Channel, frame: Vec<u8>) -> io::Result<()> {
+        if channel == Channel::Unreliable {
+            self.discard_unreliable();
+        }
+
+        if self.queued_bytes + frame.len() > self.max_queued_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                QueueFull {
+                    limit: self.max_queued_bytes,
+                },
+            ));
+        }
+
+        self.queued_bytes += frame.len();
+        self.frames.push_back((channel, frame));
+        Ok(())
+    }
+
+    /// Writes as much as the stream accepts without blocking
+    fn flush(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        while let Some((_, frame)) = self.frames.front() {
+            match stream.write(&frame[self.written..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Connection closed by peer",
+                    ))
+                }
+                Ok(n) => {
+                    self.written += n;
+                    self.queued_bytes -= n;
+                    if self.written == frame.len() {
+                        self.frames.pop_front();
+                        self.written = 0;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct TcpTransport {
+    reader: MessageReader,
+    queue: SendQueue,
+}
+
+impl TcpTransport {
+    /// Sends our handshake and switches the stream to nonblocking mode
+    pub fn new(mut stream: TcpStream) -> io::Result<Self> {
+        stream.write_all(&handshake())?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            reader: MessageReader::new(stream),
+            queue: SendQueue::new(),
+        })
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.reader.max_frame_size = max_frame_size;
+    }
+
+    /// Sending fails once more than this is waiting to be written
+    pub fn set_max_queued_bytes(&mut self, max_queued_bytes: usize) {
+        self.queue.max_queued_bytes = max_queued_bytes;
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, channel: Channel, message: &[u8]) -> io::Result<()> {
+        let frame = encode_frame(message, self.reader.max_frame_size)?;
+        self.queue.push(channel, frame)?;
+        self.queue.flush(&mut self.reader.stream)
+    }
+
+    fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        self.queue.flush(&mut self.reader.stream)?;
+        self.reader.fetch_bytes()?;
+        self.reader.iter().collect()
+    }
+
+    fn backlog(&self) -> usize {
+        self.queue.queued_bytes
+    }
+
+    fn discard_unreliable(&mut self) {
+        self.queue.discard_unreliable();
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.reader.stream.shutdown(Shutdown::Write)
+    }
+}
 
 pub struct MessageReader {
     pub stream: TcpStream,
+    pub max_frame_size: usize,
     byte_queue: VecDeque<u8>,
+    got_handshake: bool,
 }
 
 pub struct MessageIterator<'a> {
@@ -21,7 +259,9 @@ impl MessageReader {
     pub fn new(stream: TcpStream) -> Self {
         Self {
             stream,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
             byte_queue: VecDeque::new(),
+            got_handshake: false,
         }
     }
 
@@ -29,13 +269,16 @@ impl MessageReader {
         let mut buffer = [1; 64];
         loop {
             let amount = match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Connection closed by peer",
+                    ))
+                }
                 Ok(amount) => amount,
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
                 e => e?,
             };
-            if amount == 0 {
-                break Ok(());
-            }
             self.byte_queue.extend(buffer.iter().take(amount));
         }
     }
@@ -45,32 +288,76 @@ impl MessageReader {
             message_reader: self,
         }
     }
+
+    /// Returns Ok(false) until the peer's handshake has been received
+    fn check_handshake(&mut self) -> io::Result<bool> {
+        if self.got_handshake {
+            return Ok(true);
+        }
+        if self.byte_queue.len() < HANDSHAKE_SIZE {
+            return Ok(false);
+        }
+
+        let handshake = self.byte_queue.drain(0..HANDSHAKE_SIZE).collect::<Vec<_>>();
+        if handshake[..4] != PROTOCOL_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "peer is not speaking the spes protocol",
+            ));
+        }
+
+        let version = u32::from_be_bytes([handshake[4], handshake[5], handshake[6], handshake[7]]);
+        if version != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "protocol version mismatch: peer has version {}, we have {}",
+                    version, PROTOCOL_VERSION
+                ),
+            ));
+        }
+
+        self.got_handshake = true;
+        Ok(true)
+    }
 }
 
 impl Iterator for MessageIterator<'_> {
-    type Item = Vec<u8>;
+    type Item = io::Result<Vec<u8>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // We need two bytes for the length
-        if self.message_reader.byte_queue.len() < 2 {
+        match self.message_reader.check_handshake() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let byte_queue = &mut self.message_reader.byte_queue;
+        if byte_queue.len() < LENGTH_SIZE {
             return None;
         }
 
-        let length = u16::from_be_bytes([
-            self.message_reader.byte_queue[0],
-            self.message_reader.byte_queue[1],
-        ]) as usize;
+        let length =
+            u32::from_be_bytes([byte_queue[0], byte_queue[1], byte_queue[2], byte_queue[3]])
+                as usize;
+
+        // Refuse before buffering the whole thing, the stream can not be
+        // trusted after this anyway
+        if length > self.message_reader.max_frame_size {
+            return Some(Err(frame_too_large(
+                length,
+                self.message_reader.max_frame_size,
+            )));
+        }
 
         // We will not read a message until a complete message has been
         // received
-        if self.message_reader.byte_queue.len() < 2 + length {
+        if byte_queue.len() < LENGTH_SIZE + length {
             return None;
         }
 
-        self.message_reader.byte_queue.pop_front().unwrap();
-        self.message_reader.byte_queue.pop_front().unwrap();
-
-        Some(self.message_reader.byte_queue.drain(0..length).collect())
+        byte_queue.drain(0..LENGTH_SIZE);
+        Some(Ok(byte_queue.drain(0..length).collect()))
     }
 }
 
@@ -85,12 +372,499 @@ pub enum SoundEffect {
 
 #[derive(Serialize, Deserialize)]
 pub enum ServerMessage {
-    AssignId(u64),
-    GameState(crate::gamestate::GameState),
+    /// Sent when a client connects, and again with the old id when it has
+    /// resumed a session. The token lets the client resume this session if
+    /// the connection drops. The config describes the world the server runs
+    AssignId {
+        id: u64,
+        token: u64,
+        config: GameConfig,
+    },
+    /// Full state, sent when the client has no acknowledged snapshot that
+    /// the server still remembers
+    ///
+    /// last_input is the sequence number of the input the state was
+    /// simulated with, input_ticks how many ticks it had run for
+    GameState {
+        state: GameState,
+        last_input: u32,
+        input_ticks: u32,
+    },
+    GameStateDelta {
+        delta: GameStateDelta,
+        last_input: u32,
+        input_ticks: u32,
+    },
+    /// Answer to a ping with the client's send time echoed back
+    Pong { client_time: f64, server_time: f64 },
+    /// Tells the client that the connection is still alive
+    Heartbeat,
+    /// Sent right before the server drops the connection
+    Kicked { reason: String },
+    /// Answer to an AddComponent that was not built
+    BuildRejected { reason: BuildRejection },
 }
 
-#[derive(Serialize, Deserialize)]
+/// New position of an entity that is present in both the baseline and the
+/// new snapshot
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct EntityMove {
+    pub handle: RigidBodyHandle,
+    pub pos: Vec2,
+    pub angle: f32,
+    pub linvel: Vec2,
+    pub angvel: f32,
+}
+
+/// Anything in the game state that is identified by its rigid body
+pub trait Entity: Clone {
+    fn handle(&self) -> RigidBodyHandle;
+    fn movement(&self) -> EntityMove;
+    fn apply_movement(&mut self, movement: &EntityMove);
+
+    /// Whether the entity changed in a way a movement can't describe, in
+    /// which case it is sent again in full
+    fn needs_resend(&self, _old: &Self) -> bool {
+        false
+    }
+}
+
+impl Entity for Bullet {
+    fn handle(&self) -> RigidBodyHandle {
+        self.handle
+    }
+
+    fn movement(&self) -> EntityMove {
+        EntityMove {
+            handle: self.handle,
+            pos: self.pos,
+            angle: self.angle,
+            linvel: self.linvel,
+            angvel: 0.,
+        }
+    }
+
+    fn apply_movement(&mut self, movement: &EntityMove) {
+        self.pos = movement.pos;
+        self.angle = movement.angle;
+        self.linvel = movement.linvel;
+    }
+}
+
+impl Entity for Asteroid {
+    fn handle(&self) -> RigidBodyHandle {
+        self.handle
+    }
+
+    fn movement(&self) -> EntityMove {
+        EntityMove {
+            handle: self.handle,
+            pos: vec2(self.x, self.y),
+            angle: self.angle,
+            linvel: self.linvel,
+            angvel: self.angvel,
+        }
+    }
+
+    fn apply_movement(&mut self, movement: &EntityMove) {
+        self.x = movement.pos.x;
+        self.y = movement.pos.y;
+        self.angle = movement.angle;
+        self.linvel = movement.linvel;
+        self.angvel = movement.angvel;
+    }
+
+    fn needs_resend(&self, old: &Self) -> bool {
+        self.ore != old.ore || self.hp != old.hp
+    }
+}
+
+impl Entity for Component {
+    fn handle(&self) -> RigidBodyHandle {
+        self.physics_handle
+    }
+
+    fn movement(&self) -> EntityMove {
+        EntityMove {
+            handle: self.physics_handle,
+            pos: self.pos,
+            angle: self.angle,
+            linvel: self.linvel,
+            angvel: self.angvel,
+        }
+    }
+
+    fn apply_movement(&mut self, movement: &EntityMove) {
+        self.pos = movement.pos;
+        self.angle = movement.angle;
+        self.linvel = movement.linvel;
+        self.angvel = movement.angvel;
+    }
+}
+
+impl Entity for Debris {
+    fn handle(&self) -> RigidBodyHandle {
+        self.component.handle()
+    }
+
+    fn movement(&self) -> EntityMove {
+        self.component.movement()
+    }
+
+    fn apply_movement(&mut self, movement: &EntityMove) {
+        self.component.apply_movement(movement)
+    }
+}
+
+/// Changes to a list of entities between two snapshots
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EntityDelta<T> {
+    pub removed: Vec<RigidBodyHandle>,
+    pub added: Vec<T>,
+    pub moved: Vec<EntityMove>,
+}
+
+impl<T: Entity> EntityDelta<T> {
+    pub fn between(old: &[T], new: &[T]) -> Self {
+        let old_by_handle = old
+            .iter()
+            .map(|e| (e.handle(), e))
+            .collect::<HashMap<_, _>>();
+        let new_handles = new.iter().map(|e| e.handle()).collect::<Vec<_>>();
+
+        let mut delta = EntityDelta {
+            removed: old
+                .iter()
+                .map(|e| e.handle())
+                .filter(|h| !new_handles.contains(h))
+                .collect(),
+            added: vec![],
+            moved: vec![],
+        };
+
+        for entity in new {
+            match old_by_handle.get(&entity.handle()) {
+                Some(old) if entity.needs_resend(old) => {
+                    delta.removed.push(entity.handle());
+                    delta.added.push(entity.clone());
+                }
+                Some(old) => {
+                    let movement = entity.movement();
+                    if old.movement() != movement {
+                        delta.moved.push(movement);
+                    }
+                }
+                None => delta.added.push(entity.clone()),
+            }
+        }
+        delta
+    }
+
+    pub fn apply(&self, old: &[T]) -> Vec<T> {
+        let moved = self
+            .moved
+            .iter()
+            .map(|m| (m.handle, m))
+            .collect::<HashMap<_, _>>();
+
+        old.iter()
+            .filter(|e| !self.removed.contains(&e.handle()))
+            .map(|e| {
+                let mut e = e.clone();
+                if let Some(movement) = moved.get(&e.handle()) {
+                    e.apply_movement(movement);
+                }
+                e
+            })
+            .chain(self.added.iter().cloned())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.moved.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayerDelta {
+    pub id: u64,
+    /// The header of the player, only sent if it changed in a way the
+    /// client can't work out by counting down the timers
+    pub player: Option<Player>,
+    /// The parts that change nearly every update, sent on their own
+    pub energy: Option<f32>,
+    pub thruster_throttles: Option<Vec<(RigidBodyHandle, f32)>>,
+    pub components: EntityDelta<Component>,
+    pub changed_specs: Vec<(RigidBodyHandle, ComponentSpecialization)>,
+    pub changed_hp: Vec<(RigidBodyHandle, f32)>,
+}
+
+/// The player without the parts that are sent separately or derived from the
+/// rest, such as the shield points
+fn header(player: &Player) -> Player {
+    Player {
+        components: vec![],
+        thruster_throttles: vec![],
+        power: Power {
+            energy: 0.,
+            ..player.power.clone()
+        },
+        shield: Shield {
+            points: vec![],
+            ..player.shield.clone()
+        },
+        ..player.clone()
+    }
+}
+
+/// The header the player would have after the server updated it this many
+/// times without anything else happening
+fn expected_header(old: &Player, updates: u64, delta_time: f32) -> Player {
+    let mut player = old.clone();
+    // Players without a ship are not updated
+    if player.has_ship() {
+        for _ in 0..updates {
+            player.update_timers(delta_time);
+        }
+    }
+    header(&player)
+}
+
+impl PlayerDelta {
+    fn unchanged(id: u64) -> Self {
+        PlayerDelta {
+            id,
+            player: None,
+            energy: None,
+            thruster_throttles: None,
+            components: EntityDelta {
+                removed: vec![],
+                added: vec![],
+                moved: vec![],
+            },
+            changed_specs: vec![],
+            changed_hp: vec![],
+        }
+    }
+
+    /// Returns None if the player is unchanged. Updates is the number of
+    /// server updates between the states
+    pub fn between(
+        old: Option<&Player>,
+        new: &Player,
+        updates: u64,
+        delta_time: f32,
+    ) -> Option<Self> {
+        let new_header = header(new);
+        let player = match old {
+            Some(old) if expected_header(old, updates, delta_time) == new_header => None,
+            _ => Some(new_header),
+        };
+        let energy = Some(new.power.energy).filter(|e| old.map(|o| o.power.energy) != Some(*e));
+        let thruster_throttles = Some(&new.thruster_throttles)
+            .filter(|t| old.map(|o| &o.thruster_throttles) != Some(*t))
+            .cloned();
+
+        let old_components = old.map(|p| &p.components[..]).unwrap_or(&[]);
+        let changed_specs = new
+            .components
+            .iter()
+            .filter(|c| {
+                old_components
+                    .iter()
+                    .any(|o| o.physics_handle == c.physics_handle && o.spec != c.spec)
+            })
+            .map(|c| (c.physics_handle, c.spec.clone()))
+            .collect::<Vec<_>>();
+        let changed_hp = new
+            .components
+            .iter()
+            .filter(|c| {
+                old_components
+                    .iter()
+                    .any(|o| o.physics_handle == c.physics_handle && o.hp != c.hp)
+            })
+            .map(|c| (c.physics_handle, c.hp))
+            .collect::<Vec<_>>();
+
+        let components = EntityDelta::between(old_components, &new.components);
+
+        if player.is_none()
+            && energy.is_none()
+            && thruster_throttles.is_none()
+            && components.is_empty()
+            && changed_specs.is_empty()
+            && changed_hp.is_empty()
+        {
+            None
+        } else {
+            Some(PlayerDelta {
+                id: new.id,
+                player,
+                energy,
+                thruster_throttles,
+                components,
+                changed_specs,
+                changed_hp,
+            })
+        }
+    }
+
+    /// Returns None if the delta refers to a player missing from the baseline
+    /// without carrying the full player
+    pub fn apply(&self, old: Option<&Player>, updates: u64, delta_time: f32) -> Option<Player> {
+        let mut player = match (&self.player, old) {
+            (Some(player), _) => player.clone(),
+            (None, Some(old)) => expected_header(old, updates, delta_time),
+            (None, None) => return None,
+        };
+        if let Some(energy) = self.energy.or(old.map(|o| o.power.energy)) {
+            player.power.energy = energy;
+        }
+        let throttles = self.thruster_throttles.as_ref();
+        if let Some(throttles) = throttles.or(old.map(|o| &o.thruster_throttles)) {
+            player.thruster_throttles = throttles.clone();
+        }
+
+        let old_components = old.map(|p| &p.components[..]).unwrap_or(&[]);
+        player.components = self.components.apply(old_components);
+        for (handle, spec) in &self.changed_specs {
+            for component in &mut player.components {
+                if component.physics_handle == *handle {
+                    component.spec = spec.clone();
+                }
+            }
+        }
+        for (handle, hp) in &self.changed_hp {
+            for component in &mut player.components {
+                if component.physics_handle == *handle {
+                    component.hp = *hp;
+                }
+            }
+        }
+        player.place_shield_points();
+        Some(player)
+    }
+}
+
+/// The difference between a snapshot the client has acknowledged and the
+/// current state. Snapshots are identified by their tick
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameStateDelta {
+    pub baseline: u64,
+    pub tick: u64,
+    pub server_time: f64,
+    pub asteroid_timer: i32,
+    pub removed_players: Vec<u64>,
+    pub players: Vec<PlayerDelta>,
+    pub bullets: EntityDelta<Bullet>,
+    pub asteroids: EntityDelta<Asteroid>,
+    pub debris: EntityDelta<Debris>,
+    /// Hits are only sent for the tick they happened in
+    pub hits: Vec<Hit>,
+    /// Only sent if a kill was added or removed
+    pub killfeed: Option<KillFeed>,
+    pub mode: GameMode,
+}
+
+impl GameStateDelta {
+    /// Delta time is the length of a server update, which the client needs to
+    /// count down timers that are left out of the delta
+    pub fn between(old: &GameState, new: &GameState, delta_time: f32) -> Self {
+        let updates = new.tick - old.tick;
+        GameStateDelta {
+            baseline: old.tick,
+            tick: new.tick,
+            server_time: new.server_time,
+            asteroid_timer: new.asteroid_timer,
+            removed_players: old
+                .players
+                .iter()
+                .filter(|p| new.get_player_by_id(p.id).is_none())
+                .map(|p| p.id)
+                .collect(),
+            players: new
+                .players
+                .iter()
+                .filter_map(|p| {
+                    PlayerDelta::between(old.get_player_by_id(p.id), p, updates, delta_time)
+                })
+                .collect(),
+            bullets: EntityDelta::between(&old.bullets, &new.bullets),
+            asteroids: EntityDelta::between(&old.asteroids, &new.asteroids),
+            debris: EntityDelta::between(&old.debris, &new.debris),
+            hits: new.hits.clone(),
+            killfeed: Some(new.killfeed.clone()).filter(|k| *k != old.killfeed),
+            mode: new.mode.clone(),
+        }
+    }
+
+    pub fn apply(&self, old: &GameState, delta_time: f32) -> GameState {
+        let updates = self.tick - self.baseline;
+        let mut players = old
+            .players
+            .iter()
+            .filter(|p| !self.removed_players.contains(&p.id))
+            .map(|p| {
+                // Players left out of the delta still had their timers
+                // counted down
+                let unchanged = PlayerDelta::unchanged(p.id);
+                self.players
+                    .iter()
+                    .find(|d| d.id == p.id)
+                    .unwrap_or(&unchanged)
+                    .apply(Some(p), updates, delta_time)
+                    .unwrap_or_else(|| p.clone())
+            })
+            .collect::<Vec<_>>();
+
+        for delta in &self.players {
+            if old.get_player_by_id(delta.id).is_none() {
+                if let Some(player) = delta.apply(None, updates, delta_time) {
+                    players.push(player);
+                }
+            }
+        }
+
+        GameState {
+            players,
+            bullets: self.bullets.apply(&old.bullets),
+            asteroids: self.asteroids.apply(&old.asteroids),
+            debris: self.debris.apply(&old.debris),
+            asteroid_timer: self.asteroid_timer,
+            tick: self.tick,
+            server_time: self.server_time,
+            hits: self.hits.clone(),
+            killfeed: self
+                .killfeed
+                .clone()
+                .unwrap_or_else(|| old.killfeed.clone()),
+            mode: self.mode.clone(),
+        }
+    }
+}
+
+impl ServerMessage {
+    pub fn channel(&self) -> Channel {
+        match self {
+            ServerMessage::AssignId { .. }
+            | ServerMessage::Kicked { .. }
+            | ServerMessage::BuildRejected { .. } => Channel::Reliable,
+            ServerMessage::GameState { .. }
+            | ServerMessage::GameStateDelta { .. }
+            | ServerMessage::Pong { .. }
+            | ServerMessage::Heartbeat => Channel::Unreliable,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ClientInput {
+    /// Increases by one for every input the client sends
+    pub sequence: u32,
+    /// How many ticks the client simulated the input for
+    pub ticks: u32,
+
     pub x_input: f32,
     pub y_input: f32,
 
@@ -105,11 +879,14 @@ pub struct ClientInput {
     pub mouse_left: bool,
     pub mouse_right: bool,
     pub shielding: bool,
+    pub flight_assist: bool,
 }
 
 impl ClientInput {
     pub fn new() -> Self {
         ClientInput {
+            sequence: 0,
+            ticks: 0,
             x_input: 0.,
             y_input: 0.,
             mouse_x: 0.,
@@ -120,6 +897,7 @@ impl ClientInput {
             mouse_left: false,
             mouse_right: false,
             shielding: false,
+            flight_assist: false,
         }
     }
 }
@@ -130,8 +908,172 @@ pub enum ClientMessage {
     AddComponent {
         world_pos: math::Vec2,
         specialization: ComponentSpecialization,
+        /// Relative to the root, snapped to MODULE_ROTATION_STEP
+        rotation: f32,
     },
     JoinGame {
         name: String,
     },
+    /// The latest snapshot the client has received, which the server can
+    /// use as a baseline for deltas
+    AckSnapshot(u64),
+    /// Asks the server for a pong, the time is in seconds since the client
+    /// was started
+    Ping(f64),
+    /// Reclaims the ship of a dropped session. Joins with a new ship if the
+    /// session has expired
+    Resume {
+        id: u64,
+        token: u64,
+        name: String,
+    },
+    /// The player quit, so the ship can be removed right away
+    Leave,
+    /// Tells the server that the connection is still alive
+    Heartbeat,
+    /// Asks to join another team, if the mode has teams
+    SwitchTeam {
+        team: u8,
+    },
+}
+
+impl ClientMessage {
+    pub fn channel(&self) -> Channel {
+        match self {
+            ClientMessage::Input(_)
+            | ClientMessage::AckSnapshot(_)
+            | ClientMessage::Ping(_)
+            | ClientMessage::Heartbeat => Channel::Unreliable,
+            ClientMessage::AddComponent { .. }
+            | ClientMessage::JoinGame { .. }
+            | ClientMessage::Resume { .. }
+            | ClientMessage::Leave
+            | ClientMessage::SwitchTeam { .. } => Channel::Reliable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::PhysicsState;
+    use crate::player::ComponentSpecialization as CS;
+
+    fn ship(id: u64, p: &mut PhysicsState, config: &GameConfig, x: f32) -> Player {
+        let mut player = Player::new(id, format!("Player {}", id));
+        player.reset(p, config, vec2(x, 500.));
+        player
+    }
+
+    fn bullet(index: u32, x: f32) -> Bullet {
+        Bullet {
+            handle: RigidBodyHandle::from_raw_parts(index, 0),
+            owner: 1,
+            damage: 10.,
+            lifetime: 1.,
+            pos: vec2(x, 0.),
+            angle: 0.,
+            linvel: vec2(100., 0.),
+        }
+    }
+
+    /// Serialized, so that fields without PartialEq are compared too
+    fn assert_same(applied: &GameState, expected: &GameState) {
+        let encode = |state| bincode::serialize(state).unwrap();
+        assert!(encode(applied) == encode(expected), "the states differ");
+    }
+
+    /// A state at tick 10 and one three updates later with entities added,
+    /// removed and moved, and with players changed, removed and joined
+    fn states(p: &mut PhysicsState, config: &GameConfig) -> (GameState, GameState) {
+        let mut old = GameState::new(Some(p), config);
+        old.tick = 10;
+        let mut changed = ship(1, p, config, 100.);
+        changed.shield.num_points = 3;
+        changed.build_cooldown = config.build_cooldown;
+        changed.last_attacker = Some((2, 0.));
+        changed.place_shield_points();
+        old.players = vec![
+            changed,
+            ship(2, p, config, 300.),
+            ship(3, p, config, 500.),
+            Player::new(4, "Player 4".to_string()),
+        ];
+        old.bullets = vec![bullet(1000, 0.), bullet(1001, 10.)];
+        let mut lost = old.players[0].components[0].clone();
+        lost.physics_handle = RigidBodyHandle::from_raw_parts(2000, 0);
+        old.debris = vec![Debris {
+            component: lost,
+            age: 0.,
+        }];
+
+        let mut new = old.clone();
+        new.tick = 13;
+        // The server only updates players with a ship
+        for player in new.players.iter_mut().filter(|p| p.has_ship()) {
+            for _ in 0..3 {
+                player.update_timers(config.delta_time());
+            }
+        }
+
+        let changed = &mut new.players[0];
+        changed.power.energy += 5.;
+        changed.thruster_throttles = vec![(changed.core().physics_handle, 0.5)];
+        changed.aim_angle = 1.;
+        changed.shield.angle = 1.;
+        for component in &mut changed.components {
+            component.pos += vec2(3., 4.);
+        }
+        changed.components[0].hp -= 10.;
+        for component in &mut changed.components {
+            if let CS::Cannon { cooldown, .. } = &mut component.spec {
+                *cooldown = 0.5;
+            }
+        }
+        let broken = changed.components.pop().unwrap();
+        changed.place_shield_points();
+
+        new.players.retain(|player| player.id != 3);
+        new.players.push(ship(5, p, config, 700.));
+
+        new.bullets[0].pos = vec2(5., 0.);
+        new.bullets.remove(1);
+        new.bullets.push(bullet(1002, 20.));
+        new.debris = vec![Debris {
+            component: broken,
+            age: 0.,
+        }];
+        (old, new)
+    }
+
+    #[test]
+    fn deltas_turn_the_old_state_into_the_new_one() {
+        let config = GameConfig::default();
+        let mut p = PhysicsState::new();
+        let (old, new) = states(&mut p, &config);
+
+        let delta = GameStateDelta::between(&old, &new, config.delta_time());
+        assert_eq!(delta.removed_players, vec![3]);
+        assert_same(&delta.apply(&old, config.delta_time()), &new);
+    }
+
+    #[test]
+    fn counting_down_timers_sends_no_header() {
+        let config = GameConfig::default();
+        let mut p = PhysicsState::new();
+        let (old, new) = states(&mut p, &config);
+
+        let delta = GameStateDelta::between(&old, &new, config.delta_time());
+        let header_sent = |id| {
+            delta
+                .players
+                .iter()
+                .any(|player| player.id == id && player.player.is_some())
+        };
+        // Players 2 and 4 only had their timers counted down
+        assert!(header_sent(1));
+        assert!(!header_sent(2));
+        assert!(!header_sent(4));
+        assert!(header_sent(5));
+    }
 }
@@ -11,3 +11,41 @@ pub struct PhysicsState {
     pub multibody_joint_set: MultibodyJointSet,
     pub ccd_solver: CCDSolver,
 }
+
+impl PhysicsState {
+    pub fn new() -> PhysicsState {
+        PhysicsState {
+            rigid_body_set: RigidBodySet::new(),
+            collider_set: ColliderSet::new(),
+            physics_pipeline: PhysicsPipeline::new(),
+            island_manager: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            impulse_joint_set: ImpulseJointSet::new(),
+            multibody_joint_set: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+        }
+    }
+
+    /// Advances the simulation by dt seconds, which should be the tick length
+    pub fn step(&mut self, dt: f32) {
+        self.physics_pipeline.step(
+            &vector![0., 0.],
+            &IntegrationParameters {
+                dt,
+                ..IntegrationParameters::default()
+            },
+            &mut self.island_manager,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.rigid_body_set,
+            &mut self.collider_set,
+            &mut self.impulse_joint_set,
+            &mut self.multibody_joint_set,
+            &mut self.ccd_solver,
+            None,
+            &(),
+            &(),
+        );
+    }
+}
@@ -1,33 +1,41 @@
+use std::fmt;
+
 use serde_derive::{Deserialize, Serialize};
 
+use crate::config::{FriendlyFire, GameConfig};
 use crate::gamestate::Bullet;
-use crate::math::{vec2, Vec2};
+use crate::killfeed::KillCause;
+use crate::math::{self, vec2, Vec2};
 use crate::messages::ClientInput;
 use crate::physics::PhysicsState;
+use crate::power::{Power, PowerSystem};
+use crate::stats::Stats;
 use crate::constants;
 
-use macroquad::rand::gen_range;
 use rapier2d::prelude::*;
 use rapier2d::prelude::{RigidBodyHandle, RigidBodySet};
 
-// one degree
-const SHIELD_POINT_SPACING: f32 = 1.;
-
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Component {
     pub pos: Vec2,
     pub angle: f32,
+    pub linvel: Vec2,
+    pub angvel: f32,
     pub physics_handle: RigidBodyHandle,
     pub spec: ComponentSpecialization,
+    pub hp: f32,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum ComponentSpecialization {
     Root,
     Shield,
     Thrusters,
     Reactionwheel{angle: f32},
     Cannon { cooldown: f32, aim: bool },
+    Generator,
+    Battery,
+    MiningLaser,
 }
 
 impl ComponentSpecialization {
@@ -39,14 +47,179 @@ impl ComponentSpecialization {
             ComponentSpecialization::Reactionwheel{angle: 0.},
             ComponentSpecialization::Cannon{cooldown: 0., aim: true},
             ComponentSpecialization::Cannon{cooldown: 0., aim: false},
+            ComponentSpecialization::Generator,
+            ComponentSpecialization::Battery,
+            ComponentSpecialization::MiningLaser,
         ]
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComponentSpecialization::Root => "Root",
+            ComponentSpecialization::Shield => "Shield",
+            ComponentSpecialization::Thrusters => "Thrusters",
+            ComponentSpecialization::Reactionwheel{..} => "Reaction wheel",
+            ComponentSpecialization::Cannon{aim: true, ..} => "Turret",
+            ComponentSpecialization::Cannon{aim: false, ..} => "Fixed cannon",
+            ComponentSpecialization::Generator => "Generator",
+            ComponentSpecialization::Battery => "Battery",
+            ComponentSpecialization::MiningLaser => "Mining laser",
+        }
+    }
+
+    // The module as it is when it is first built, without any state a client
+    // may have sent along
+    pub fn reset_state(&self) -> ComponentSpecialization {
+        match self {
+            ComponentSpecialization::Cannon{aim, ..} => {
+                ComponentSpecialization::Cannon{cooldown: 0., aim: *aim}
+            }
+            ComponentSpecialization::Reactionwheel{..} => {
+                ComponentSpecialization::Reactionwheel{angle: 0.}
+            }
+            other => other.clone(),
+        }
+    }
+
+    pub fn max_hp(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Root => 200.,
+            ComponentSpecialization::Shield => 60.,
+            ComponentSpecialization::Thrusters => 80.,
+            ComponentSpecialization::Reactionwheel{..} => 80.,
+            ComponentSpecialization::Cannon{..} => 100.,
+            ComponentSpecialization::Generator => 60.,
+            ComponentSpecialization::Battery => 60.,
+            ComponentSpecialization::MiningLaser => 60.,
+        }
+    }
+
+    // Ore it takes to build
+    pub fn cost(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Root => 0.,
+            ComponentSpecialization::Shield => 30.,
+            ComponentSpecialization::Thrusters => 20.,
+            ComponentSpecialization::Reactionwheel{..} => 20.,
+            ComponentSpecialization::Cannon{aim: true, ..} => 40.,
+            ComponentSpecialization::Cannon{aim: false, ..} => 30.,
+            ComponentSpecialization::Generator => 50.,
+            ComponentSpecialization::Battery => 30.,
+            ComponentSpecialization::MiningLaser => 30.,
+        }
+    }
+
+    // Fraction of incoming damage that is absorbed
+    pub fn armor(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Root => 0.5,
+            ComponentSpecialization::Shield => 0.2,
+            _ => 0.,
+        }
+    }
+
+    // Damage done by the bullets this component fires
+    pub fn damage(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Cannon{aim: true, ..} => 10.,
+            ComponentSpecialization::Cannon{aim: false, ..} => 20.,
+            _ => 0.,
+        }
+    }
+
+    pub fn power_system(&self) -> Option<PowerSystem> {
+        match self {
+            ComponentSpecialization::Shield => Some(PowerSystem::Shield),
+            ComponentSpecialization::Reactionwheel{..} => Some(PowerSystem::Steering),
+            ComponentSpecialization::Thrusters => Some(PowerSystem::Engines),
+            ComponentSpecialization::Cannon{..} => Some(PowerSystem::Weapons),
+            ComponentSpecialization::MiningLaser => Some(PowerSystem::Weapons),
+            _ => None,
+        }
+    }
+
+    // Energy per second used while active, cannons use it per shot instead
+    pub fn power_draw(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Shield => 8.,
+            ComponentSpecialization::Reactionwheel{..} => 5.,
+            ComponentSpecialization::Thrusters => 6.,
+            ComponentSpecialization::Cannon{..} => 5.,
+            ComponentSpecialization::MiningLaser => 6.,
+            _ => 0.,
+        }
+    }
+
+    // Energy per second
+    pub fn power_generation(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Root => 10.,
+            ComponentSpecialization::Generator => 15.,
+            _ => 0.,
+        }
+    }
+
+    pub fn energy_capacity(&self) -> f32 {
+        match self {
+            ComponentSpecialization::Root => 100.,
+            ComponentSpecialization::Battery => 100.,
+            _ => 0.,
+        }
+    }
+}
+
+/// Why the server refused to build a module
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum BuildRejection {
+    NotAddable,
+    InvalidPlacement,
+    Dead,
+    NotConnected,
+    Overlapping,
+    TooManyModules { limit: usize },
+    CantAfford { cost: f32 },
+    Cooldown { remaining: f32 },
+}
+
+impl BuildRejection {
+    /// Clients can send any numbers, NaN or infinity would end up in the
+    /// physics
+    pub fn check_placement(pos: Vec2, rotation: f32) -> Result<(), BuildRejection> {
+        if pos.x.is_finite() && pos.y.is_finite() && rotation.is_finite() {
+            Ok(())
+        } else {
+            Err(BuildRejection::InvalidPlacement)
+        }
+    }
+}
+
+impl fmt::Display for BuildRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildRejection::NotAddable => write!(f, "That module can't be built"),
+            BuildRejection::InvalidPlacement => write!(f, "That is not a place in the world"),
+            BuildRejection::Dead => write!(f, "Can't build while dead"),
+            BuildRejection::NotConnected => write!(f, "Modules have to touch the ship"),
+            BuildRejection::Overlapping => write!(f, "Something is already there"),
+            BuildRejection::TooManyModules { limit } => {
+                write!(f, "Ships can't have more than {} modules", limit)
+            }
+            BuildRejection::CantAfford { cost } => {
+                write!(f, "That costs {:.0} ore", cost)
+            }
+            BuildRejection::Cooldown { remaining } => {
+                write!(f, "Wait {:.1}s before building again", remaining)
+            }
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Player {
     pub id: u64,
     pub name: String,
+    // None outside of team modes
+    pub team: Option<u8>,
 
     pub input_x: f32,
     pub input_y: f32,
@@ -62,28 +235,54 @@ pub struct Player {
     pub shoot: bool,
 
     pub is_building: bool,
+    // seconds until the ship can build again
+    pub build_cooldown: f32,
 
     pub shield: Shield,
     pub shielding: bool,
 
+    // fire the thrusters to hold the ship still when there is no input
+    pub flight_assist: bool,
+    // how hard each thruster fired during the last update, between -1 and 1
+    pub thruster_throttles: Vec<(RigidBodyHandle, f32)>,
+    pub power: Power,
+    // mined out of asteroids and spent on modules
+    pub ore: f32,
+    // (mining laser index in components, handle of the asteroid it mines)
+    pub mining_beams: Vec<(usize, RigidBodyHandle)>,
+
+    // (id of the last player to damage the ship, seconds since)
+    pub last_attacker: Option<(u64, f32)>,
+    // what finished the ship off, set when it dies
+    pub death_cause: Option<KillCause>,
     pub requesting_death: bool,
+    // seconds until a dead ship can respawn
+    pub respawn_timer: f32,
+    // seconds left during which a new ship takes no damage
+    pub spawn_protection: f32,
+
+    pub stats: Stats,
 }
 
 impl Component {
 
+    /// Removes the rigid body along with its collider and the joint holding
+    /// it to the ship
     pub fn destroy_physics(&mut self, p: &mut PhysicsState) {
         p.rigid_body_set.remove(self.physics_handle, &mut p.island_manager, &mut p.collider_set, &mut p.impulse_joint_set, &mut p.multibody_joint_set, true);
     }
 
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Shield {
     pub colliders: Vec<RigidBodyHandle>,
     pub points: Vec<Point<Real>>,
     pub angle: f32,
     pub num_points: usize,
     pub radius: f32,
+    // whether the shield is raised and blocks bullets
+    pub active: bool,
 }
 
 impl Shield {
@@ -93,14 +292,12 @@ impl Shield {
             points: vec![],
             angle: 0.,
             num_points: 0,
-            radius: 100.,
+            radius: constants::SHIELD_BASE_RADIUS,
+            active: false,
         }
     }
 
     fn init_points(&mut self, p: &mut PhysicsState) {
-        if self.num_points == 0 {
-            return;
-        }
         self.points = vec![];
         for rb in &self.colliders {
             p.rigid_body_set.remove(*rb, &mut p.island_manager, &mut p.collider_set, &mut p.impulse_joint_set, &mut p.multibody_joint_set, true);
@@ -134,31 +331,36 @@ impl Shield {
         self.update(p, ppos);
     }
 
-    fn update(&mut self, p: &mut PhysicsState, ppos: Vec2) {
-        let mut i = 0;
-        for a in -((self.num_points / 2) as i32)..(self.num_points as i32 / 2) {
-            let angle = self.angle + SHIELD_POINT_SPACING * (a as f32) / (self.num_points as f32);
-            let x = self.radius * angle.cos() + ppos.x;
-            let y = self.radius * angle.sin() + ppos.y;
-
-            let rb = p.rigid_body_set.get_mut(self.colliders[i]).unwrap();
-            rb.set_translation(vector!(x, y), true);
-
-            self.points[i].x = x;
-            self.points[i].y = y;
+    /// Where the points of the arc are around a ship at ppos
+    pub fn arc_points(&self, ppos: Vec2) -> Vec<Point<Real>> {
+        // Neighbouring points touch so that nothing slips through the arc
+        let spacing = constants::SHIELD_SEGMENT_RADIUS * 2. / self.radius;
+        let first = -((self.num_points / 2) as i32);
+        (first..first + self.num_points as i32)
+            .map(|a| {
+                let angle = self.angle + spacing * a as f32;
+                point!(self.radius * angle.cos() + ppos.x, self.radius * angle.sin() + ppos.y)
+            })
+            .collect()
+    }
 
-            i += 1; // deal with it the sushi is soon here
+    fn update(&mut self, p: &mut PhysicsState, ppos: Vec2) {
+        self.points = self.arc_points(ppos);
+        for (handle, point) in self.colliders.iter().zip(&self.points) {
+            let rb = p.rigid_body_set.get_mut(*handle).unwrap();
+            rb.set_translation(vector!(point.x, point.y), true);
         }
-
     }
 }
 
 impl Player {
-    pub fn new(id: u64, name: String, p: &mut PhysicsState) -> Player {
+    /// A player without a ship, reset gives it one
+    pub fn new(id: u64, name: String) -> Player {
         let shield = Shield::new();
-        let mut player = Player {
+        Player {
             id,
             name,
+            team: None,
 
             input_x: 0.,
             input_y: 0.,
@@ -176,17 +378,35 @@ impl Player {
             shield,
 
             is_building: false,
+            build_cooldown: 0.,
 
             shielding: false,
 
+            flight_assist: false,
+            thruster_throttles: vec![],
+
+            power: Power::new(),
+            ore: 0.,
+            mining_beams: vec![],
+
+            last_attacker: None,
+            death_cause: None,
             requesting_death: false,
-        };
+            respawn_timer: 0.,
+            spawn_protection: 0.,
 
-        player.reset(p);
-        player
+            stats: Stats::new(),
+        }
     }
 
-    pub fn reset(&mut self, p: &mut PhysicsState) {
+    /// Players that join during an elimination round have no ship until the
+    /// next one
+    pub fn has_ship(&self) -> bool {
+        !self.components.is_empty()
+    }
+
+    /// Replaces the ship with a new one at the spawn point
+    pub fn reset(&mut self, p: &mut PhysicsState, config: &GameConfig, spawn: Vec2) {
         self.input_x = 0.;
         self.input_y = 0.;
 
@@ -200,70 +420,121 @@ impl Player {
         self.shoot = false;
 
         self.is_building = false;
+        self.build_cooldown = 0.;
 
         self.shielding = false;
+        self.shield.active = false;
+
+        self.flight_assist = false;
+        self.thruster_throttles = vec![];
 
+        self.last_attacker = None;
+        self.death_cause = None;
         self.requesting_death = false;
+        self.respawn_timer = 0.;
+        self.spawn_protection = config.spawn_protection;
+        self.stats.time_alive = 0.;
 
         for comp in &mut self.components {
-            //comp.destroy_physics(p);
+            comp.destroy_physics(p);
         }
         self.components = vec![];
-        self.add_component(
-            ComponentSpecialization::Root,
-            p,
-            (constants::WORLD_SIZE / 2., constants::WORLD_SIZE / 2.)
-        );
-        self.add_component(
-            ComponentSpecialization::Cannon {
-                cooldown: 0.0,
-                aim: [true, false][gen_range(0, 1)],
-            },
-            p,
-            (
-                constants::WORLD_SIZE / 2.,
-                constants::WORLD_SIZE / 2. + constants::MODULE_RADIUS * 2.,
-            ),
-        );
-        self.add_component(
-            ComponentSpecialization::Thrusters,
-            p,
-            (
-                constants::WORLD_SIZE / 2.,
-                constants::WORLD_SIZE / 2. + constants::MODULE_RADIUS * 4.,
-            ),
-        );
-        self.add_component(
-            ComponentSpecialization::Shield,
-            p,
-            (
-                constants::WORLD_SIZE / 2. + constants::MODULE_RADIUS * 2.,
-                constants::WORLD_SIZE / 2.,
-            ),
-        );
-        self.add_component(
-            ComponentSpecialization::Reactionwheel{angle: 0.},
-            p,
-            (
-                constants::WORLD_SIZE / 2. - constants::MODULE_RADIUS * 2.,
-                constants::WORLD_SIZE / 2.,
-            ),
-        );
+        let (x, y) = (spawn.x, spawn.y);
+        self.add_component(ComponentSpecialization::Root, p, config, (x, y), 0.);
+        for module in &config.starting_loadout {
+            self.add_component(
+                module.module.clone(),
+                p,
+                config,
+                (
+                    x + module.x * constants::MODULE_RADIUS * 2.,
+                    y + module.y * constants::MODULE_RADIUS * 2.,
+                ),
+                math::snap_angle(module.rotation, constants::MODULE_ROTATION_STEP),
+            );
+        }
+
+        self.power = Power::new();
+        self.power.energy = self.energy_capacity();
+        self.ore = config.starting_ore;
+        self.mining_beams = vec![];
     }
 
+    /// Checks that the module may be built at the world position
+    pub fn check_build(
+        &self,
+        spec: &ComponentSpecialization,
+        pos: Vec2,
+        config: &GameConfig,
+    ) -> Result<(), BuildRejection> {
+        // Only the kind of module matters, not its state
+        let addable = ComponentSpecialization::addable()
+            .iter()
+            .any(|a| std::mem::discriminant(a) == std::mem::discriminant(spec));
+        if !addable {
+            return Err(BuildRejection::NotAddable);
+        }
+        if self.requesting_death {
+            return Err(BuildRejection::Dead);
+        }
+        if self.build_cooldown > 0. {
+            return Err(BuildRejection::Cooldown {
+                remaining: self.build_cooldown,
+            });
+        }
+        if self.components.len() >= config.max_modules {
+            return Err(BuildRejection::TooManyModules {
+                limit: config.max_modules,
+            });
+        }
+        if self.ore < spec.cost() {
+            return Err(BuildRejection::CantAfford { cost: spec.cost() });
+        }
+
+        let overlapping = self
+            .components
+            .iter()
+            .any(|c| (c.pos - pos).norm() < constants::MODULE_RADIUS * 2.);
+        if overlapping {
+            return Err(BuildRejection::Overlapping);
+        }
+        let connected = self
+            .components
+            .iter()
+            .any(|c| (c.pos - pos).norm() < constants::MODULE_LINK_DISTANCE);
+        if !connected {
+            return Err(BuildRejection::NotConnected);
+        }
+        Ok(())
+    }
+
+    /// Adds a module at the world position, turned by rotation radians
+    /// relative to the root
     pub fn add_component(
         &mut self,
         specialization: ComponentSpecialization,
         p: &mut PhysicsState,
-        (world_x, world_y): (f32, f32)
+        config: &GameConfig,
+        (world_x, world_y): (f32, f32),
+        rotation: f32,
     ) {
+        let root_transform = self
+            .components
+            .first()
+            .map(|root| *p.rigid_body_set.get(root.physics_handle).unwrap().position())
+            .unwrap_or_else(Isometry::identity);
+
         let rb = RigidBodyBuilder::dynamic()
             .translation(vector![world_x, world_y])
+            .rotation(root_transform.rotation.angle() + rotation)
             .build();
 
         let local_transform = rb.position().clone();
 
-        let mut collider_builder = ColliderBuilder::ball(32.).restitution(0.2).friction(0.);
+        let mut collider_builder = ColliderBuilder::ball(32.)
+            .restitution(0.2)
+            .friction(0.)
+            .collision_groups(self.collision_groups(config));
         if !self.components.is_empty() {
             collider_builder = collider_builder.density(0.001);
         }
@@ -276,7 +547,10 @@ impl Player {
         let new = Component {
             pos: vec2(world_x, world_y),
             physics_handle: body_handle,
-            angle: 0.,
+            angle: root_transform.rotation.angle() + rotation,
+            linvel: vec2(0., 0.),
+            angvel: 0.,
+            hp: specialization.max_hp(),
             spec: specialization,
         };
 
@@ -284,13 +558,9 @@ impl Player {
 
         // Joint if we are adding a sub-component
         if self.components.len() != 1 {
-            let root_transform = p
-                .rigid_body_set
-                .get(self.components[0].physics_handle)
-                .unwrap()
-                .position();
+            let anchor = root_transform.inverse_transform_point(&point![world_x, world_y]);
             let joint = FixedJointBuilder::new()
-                .local_anchor1(root_transform.inverse_transform_point(&point![world_x, world_y]))
+                .local_frame1(Isometry::new(anchor.coords, rotation))
                 .local_anchor2(point![0., 0.]);
 
             p.multibody_joint_set.insert(
@@ -302,8 +572,24 @@ impl Player {
         }
     }
 
+    /// Ships of the same team fly through each other when friendly fire
+    /// passes through, everything else collides
+    fn collision_groups(&self, config: &GameConfig) -> InteractionGroups {
+        match self.team {
+            Some(team) if config.friendly_fire == FriendlyFire::PassThrough => {
+                let group = Group::from_bits_truncate(1 << (team % 32));
+                InteractionGroups::new(group, Group::ALL ^ group)
+            }
+            _ => InteractionGroups::all(),
+        }
+    }
+
     pub fn set_input(&mut self, i: &ClientInput) {
         self.input_x = i.x_input;
+        // All a dead player can do is ask to respawn
+        if self.requesting_death {
+            return;
+        }
         self.input_y = i.y_input;
         self.mouse_x = i.mouse_x;
         self.mouse_y = i.mouse_y;
@@ -311,13 +597,223 @@ impl Player {
         self.aim_angle = i.aim_angle;
         self.shielding = i.shielding;
         self.mouse_world_pos = i.mouse_world;
+        self.flight_assist = i.flight_assist;
+    }
+
+    /// Removes the ship and its shield from the physics world
+    pub fn destroy_physics(&mut self, p: &mut PhysicsState) {
+        for comp in &mut self.components {
+            comp.destroy_physics(p);
+        }
+        for rb in &self.shield.colliders {
+            p.rigid_body_set.remove(*rb, &mut p.island_manager, &mut p.collider_set, &mut p.impulse_joint_set, &mut p.multibody_joint_set, true);
+        }
+    }
+
+    /// The modules next to each module, by index. Modules that are touching
+    /// hold each other together
+    pub fn connectivity(&self) -> Vec<Vec<usize>> {
+        self.components
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                self.components
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, b)| {
+                        i != *j && (a.pos - b.pos).norm() < constants::MODULE_LINK_DISTANCE
+                    })
+                    .map(|(j, _)| j)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Removes the modules that were shot to pieces. Losing the root kills the
+    /// ship. Returns the modules that are no longer connected to the root,
+    /// their bodies are cut loose from the ship
+    pub fn remove_destroyed_components(&mut self, p: &mut PhysicsState) -> Vec<Component> {
+        if !self.has_ship() || self.core().hp <= 0. {
+            self.requesting_death = true;
+            return vec![];
+        }
+
+        let mut i = 1;
+        let mut destroyed = false;
+        while i < self.components.len() {
+            if self.components[i].hp <= 0. {
+                self.components.remove(i).destroy_physics(p);
+                destroyed = true;
+            } else {
+                i += 1;
+            }
+        }
+        if !destroyed {
+            return vec![];
+        }
+
+        let links = self.connectivity();
+        let mut connected = vec![false; self.components.len()];
+        let mut queue = vec![0];
+        connected[0] = true;
+        while let Some(i) = queue.pop() {
+            for &j in &links[i] {
+                if !connected[j] {
+                    connected[j] = true;
+                    queue.push(j);
+                }
+            }
+        }
+
+        let root_pos = self.position();
+        let mut detached = vec![];
+        for (component, connected) in std::mem::take(&mut self.components)
+            .into_iter()
+            .zip(connected)
+        {
+            if connected {
+                self.components.push(component);
+                continue;
+            }
+
+            p.multibody_joint_set
+                .remove_joints_attached_to_rigid_body(component.physics_handle);
+            let rb = p.rigid_body_set.get_mut(component.physics_handle).unwrap();
+            let away = (component.pos - root_pos).normalize() * constants::DEBRIS_SPEED;
+            rb.set_linvel(rb.linvel() + vector![away.x, away.y], true);
+            detached.push(component);
+        }
+        detached
+    }
+
+    /// Damages a module, remembering who did it. Destroying the root kills the
+    /// ship. Returns the damage done, which is none while the ship is
+    /// protected after spawning
+    pub fn damage_component(
+        &mut self,
+        index: usize,
+        damage: f32,
+        cause: KillCause,
+        attacker: Option<u64>,
+    ) -> f32 {
+        if self.spawn_protection > 0. {
+            return 0.;
+        }
+
+        let component = &mut self.components[index];
+        component.hp -= damage;
+        self.stats.damage_taken += damage;
+        if let Some(attacker) = attacker {
+            self.last_attacker = Some((attacker, 0.));
+        }
+        if index == 0 && component.hp <= 0. && self.death_cause.is_none() {
+            self.death_cause = Some(cause);
+        }
+        damage
+    }
+
+    /// Attaches a loose module to the free spot on the ship's grid that is
+    /// closest to it. Returns false if the spot is taken or the ship is full
+    pub fn salvage(
+        &mut self,
+        module: &Component,
+        p: &mut PhysicsState,
+        config: &GameConfig,
+    ) -> bool {
+        // Salvaged modules count towards the limit like built ones
+        if self.components.len() >= config.max_modules {
+            return false;
+        }
+        let root = *p
+            .rigid_body_set
+            .get(self.core().physics_handle)
+            .unwrap()
+            .position();
+        let spacing = constants::MODULE_RADIUS * 2.;
+        let local = root.inverse_transform_point(&point![module.pos.x, module.pos.y]);
+        let slot = root.transform_point(&point![
+            (local.x / spacing).round() * spacing,
+            (local.y / spacing).round() * spacing
+        ]);
+
+        let taken = self
+            .components
+            .iter()
+            .any(|c| (c.pos - vec2(slot.x, slot.y)).norm() < constants::MODULE_RADIUS);
+        if taken {
+            return false;
+        }
+
+        let rotation = math::snap_angle(
+            module.angle - root.rotation.angle(),
+            constants::MODULE_ROTATION_STEP,
+        );
+        self.add_component(module.spec.clone(), p, config, (slot.x, slot.y), rotation);
+        self.components.last_mut().unwrap().hp = module.hp;
+        true
     }
 
     pub fn set_num_shield_points(&mut self, num_points: usize, p: &mut PhysicsState) {
         self.shield.set_num_points(num_points, p, self.position());
     }
 
+    pub fn energy_capacity(&self) -> f32 {
+        self.components.iter().map(|c| c.spec.energy_capacity()).sum()
+    }
+
+    /// Works out which systems get energy during this update
+    pub fn update_power(&mut self, delta: f32) {
+        use ComponentSpecialization as CS;
+
+        self.power.generation = self.components.iter().map(|c| c.spec.power_generation()).sum();
+        self.power.capacity = self.energy_capacity();
+
+        // Once the shield runs dry it needs some energy before it comes back
+        // up, otherwise it flickers
+        let min_energy = if self.shield.active { 0. } else { constants::SHIELD_MIN_ENERGY };
+        let raise_shield =
+            self.shielding && self.shield.num_points > 0 && self.power.energy > min_energy;
+
+        let requests = self
+            .components
+            .iter()
+            .filter_map(|c| {
+                let (active, energy) = match c.spec {
+                    CS::Shield => (raise_shield, c.spec.power_draw() * delta),
+                    CS::Reactionwheel{..} => (
+                        self.input_x != 0. || self.flight_assist,
+                        c.spec.power_draw() * delta,
+                    ),
+                    CS::Thrusters => (
+                        self.input_y != 0. || self.flight_assist,
+                        c.spec.power_draw() * delta,
+                    ),
+                    CS::Cannon{cooldown, ..} => (self.shoot && cooldown <= 0., c.spec.power_draw()),
+                    CS::MiningLaser => (self.shoot, c.spec.power_draw() * delta),
+                    _ => (false, 0.),
+                };
+                c.spec.power_system().filter(|_| active).map(|system| (system, energy))
+            })
+            .collect::<Vec<_>>();
+        self.power.distribute(delta, &requests);
+
+        self.shield.active = raise_shield && self.power.is_powered(PowerSystem::Shield);
+    }
+
     pub fn shield_update(&mut self, p: &mut PhysicsState) {
+        // Every shield module makes the arc wider and pushes it further out
+        let modules = self
+            .components
+            .iter()
+            .filter(|c| c.spec == ComponentSpecialization::Shield)
+            .count();
+        let num_points = modules * constants::SHIELD_POINTS_PER_MODULE;
+        if num_points != self.shield.num_points {
+            self.shield.radius = constants::SHIELD_BASE_RADIUS
+                + modules.saturating_sub(1) as f32 * constants::SHIELD_RADIUS_PER_MODULE;
+            self.set_num_shield_points(num_points, p);
+        }
+
         self.shield.update_mouse(self.aim_angle, p, self.position());
     }
 
@@ -325,46 +821,188 @@ impl Player {
         &mut self,
         delta: f32,
         bullets: &mut Vec<Bullet>,
-        p: &mut PhysicsState
+        p: &mut PhysicsState,
+        config: &GameConfig,
     ) {
-        let root_handle = self
-            .components
-            .first()
-            .expect("Player without a component")
-            .physics_handle;
-
-        let rb = p.rigid_body_set
-            .get_mut(root_handle)
-            .expect(&format!("No rigid body for player {}", self.id));
-
-        rb.reset_forces(true);
-        rb.reset_torques(true);
-        rb.add_force(
-            rb.position().rotation * vector!(0., -self.input_y) * 1000_000.,
-            true,
-        );
-
-        let bonus_torque = (self.components.len() * 15000) as f32;
-        rb.add_torque(self.input_x * 500_0000. + bonus_torque, true);
-        // rb.apply_impulse_at_point(
-        //     rb.position().rotation * vector!(0., -self.input_y) * 100_000.,
-        //     rb.position().translation.vector.into(),
-        //     true,
-        // );
+        if !self.has_ship() {
+            return;
+        }
+        // The wreck drifts without power until the player respawns, so it
+        // can't thrust, shoot or shield any more
+        if self.requesting_death {
+            self.reset_thrust(p);
+            self.shield.active = false;
+            self.update_timers(delta);
+            return;
+        }
+        self.update_power(delta);
+        self.apply_thrust(p);
 
         self.update_components(&mut p.rigid_body_set, bullets, delta);
 
         self.shield_update(p);
+        self.update_timers(delta);
 
         let pos = self.position();
-        self.requesting_death = self.requesting_death
-            || pos.y < 0.
-            || pos.y > constants::WORLD_SIZE
+        let out_of_bounds = pos.y < 0.
+            || pos.y > config.world_size
             || pos.x < 0.
-            || pos.x > constants::WORLD_SIZE;
+            || pos.x > config.world_size;
+        if out_of_bounds && !self.requesting_death {
+            self.requesting_death = true;
+            self.death_cause = Some(KillCause::OutOfBounds);
+        }
+    }
+
+    /// Counts down the timers of the ship by one update. Clients repeat it to
+    /// bring the timers of an older snapshot up to date
+    pub fn update_timers(&mut self, delta: f32) {
+        self.build_cooldown = (self.build_cooldown - delta).max(0.);
+        if let Some((_, age)) = &mut self.last_attacker {
+            *age += delta;
+        }
+        if !self.requesting_death {
+            self.stats.add_time_alive(delta);
+        }
+        self.respawn_timer = (self.respawn_timer - delta).max(0.);
+        // Shooting gives up the protection
+        if self.shoot {
+            self.spawn_protection = 0.;
+        }
+        self.spawn_protection = (self.spawn_protection - delta).max(0.);
+    }
 
-        if self.requesting_death && self.input_x > 0. {
-            self.reset(p);
+    /// The player credited with killing the ship, if anyone damaged it
+    /// recently enough
+    pub fn killer(&self) -> Option<u64> {
+        self.last_attacker
+            .filter(|(id, age)| *id != self.id && *age < constants::KILL_CREDIT_TIME)
+            .map(|(id, _)| id)
+    }
+
+    /// Takes away the forces of the last update, which rapier keeps applying
+    /// otherwise
+    fn reset_thrust(&mut self, p: &mut PhysicsState) {
+        for component in &self.components {
+            let rb = p.rigid_body_set
+                .get_mut(component.physics_handle)
+                .expect(&format!("No rigid body for player {}", self.id));
+            rb.reset_forces(true);
+            rb.reset_torques(true);
+        }
+        self.thruster_throttles = vec![];
+    }
+
+    /// Fires every thruster along its own direction at its own position, and
+    /// turns the ship with the reaction wheels. With flight assist the
+    /// throttles are worked out to give the ship the motion the pilot asks for
+    fn apply_thrust(&mut self, p: &mut PhysicsState) {
+        self.reset_thrust(p);
+
+        let root = p.rigid_body_set.get(self.core().physics_handle).unwrap();
+        let rotation = *root.rotation();
+        let linvel = *root.linvel();
+        let angvel = root.angvel();
+
+        // The root is far heavier than the modules, but they still shift the
+        // center of mass
+        let bodies = self
+            .components
+            .iter()
+            .map(|c| (c.pos, p.rigid_body_set.get(c.physics_handle).unwrap().mass()))
+            .collect::<Vec<_>>();
+        let mass = bodies.iter().map(|(_, m)| m).sum::<f32>();
+        let center = bodies
+            .iter()
+            .fold(vec2(0., 0.), |acc, (pos, m)| acc + *pos * (*m / mass));
+        let inertia = bodies
+            .iter()
+            .map(|(pos, m)| {
+                m * ((*pos - center).norm().powi(2) + constants::MODULE_RADIUS.powi(2) / 2.)
+            })
+            .sum::<f32>();
+
+        // Direction and lever arm of every thruster
+        let thrusters = self
+            .components
+            .iter()
+            .filter(|c| c.spec == ComponentSpecialization::Thrusters)
+            .map(|c| {
+                let direction = Vec2::from_direction(c.angle - std::f32::consts::PI / 2., 1.);
+                let arm = c.pos - center;
+                (c.physics_handle, direction, arm.x * direction.y - arm.y * direction.x)
+            })
+            .collect::<Vec<_>>();
+        let wheels = self
+            .components
+            .iter()
+            .filter(|c| matches!(c.spec, ComponentSpecialization::Reactionwheel{..}))
+            .count();
+        let max_torque = wheels as f32 * constants::REACTION_WHEEL_TORQUE;
+
+        let engines = self.power.is_powered(PowerSystem::Engines);
+        let steering = self.power.is_powered(PowerSystem::Steering);
+
+        let (throttles, wheel_torque) = if self.flight_assist {
+            let force = if self.input_y != 0. {
+                let forward = rotation * vector!(0., -self.input_y);
+                vec2(forward.x, forward.y) * thrusters.len() as f32 * constants::THRUSTER_FORCE
+            } else {
+                vec2(-linvel.x, -linvel.y) * mass * constants::FLIGHT_ASSIST_DAMPING
+            };
+            let torque = if self.input_x != 0. {
+                let thruster_torque = constants::THRUSTER_FORCE * constants::MODULE_RADIUS;
+                self.input_x * (max_torque + thrusters.len() as f32 * thruster_torque)
+            } else {
+                -angvel * inertia * constants::FLIGHT_ASSIST_DAMPING
+            };
+
+            // The wheels take what torque they can and the thrusters make up
+            // the rest
+            let wheel_torque = if steering { torque.max(-max_torque).min(max_torque) } else { 0. };
+            let thruster_torque = torque - wheel_torque;
+
+            // Least squares split of the force and torque over the thrusters
+            let force_dir = if force.norm() > 0. { force.normalize() } else { vec2(0., 0.) };
+            let along = thrusters.iter().map(|(_, d, _)| d.dot(force_dir).powi(2)).sum::<f32>();
+            let arms = thrusters.iter().map(|(_, _, arm)| arm.powi(2)).sum::<f32>();
+            let throttles = thrusters
+                .iter()
+                .map(|(handle, direction, arm)| {
+                    let mut throttle = 0.;
+                    if along > 0. {
+                        throttle += direction.dot(force) / (along * constants::THRUSTER_FORCE);
+                    }
+                    if arms > 0. {
+                        throttle += arm * thruster_torque / (arms * constants::THRUSTER_FORCE);
+                    }
+                    (*handle, throttle.max(-1.).min(1.))
+                })
+                .collect::<Vec<_>>();
+            (throttles, wheel_torque)
+        } else {
+            let throttles = thrusters
+                .iter()
+                .map(|(handle, _, _)| (*handle, self.input_y))
+                .collect::<Vec<_>>();
+            (throttles, self.input_x * max_torque)
+        };
+
+        self.thruster_throttles = if engines { throttles } else { vec![] };
+        let fired = self.thruster_throttles.iter().zip(&thrusters);
+        for ((handle, throttle), (_, direction, _)) in fired {
+            let force = *direction * (throttle * constants::THRUSTER_FORCE);
+            p.rigid_body_set
+                .get_mut(*handle)
+                .unwrap()
+                .add_force(vector!(force.x, force.y), true);
+        }
+
+        if steering {
+            p.rigid_body_set
+                .get_mut(self.core().physics_handle)
+                .unwrap()
+                .add_torque(wheel_torque, true);
         }
     }
 
@@ -375,11 +1013,12 @@ impl Player {
         delta: f32,
     ) {
         use ComponentSpecialization as CS;
+        let weapons = self.power.is_powered(PowerSystem::Weapons);
         self.components = self
             .components
             .iter()
             .filter_map(|c| match c.spec {
-                CS::Cannon { cooldown, aim } if cooldown <= 0.0 && self.shoot => {
+                CS::Cannon { cooldown, aim } if cooldown <= 0.0 && self.shoot && weapons => {
                     let rb = rbs.get(c.physics_handle).unwrap();
                     let rb_vel = rb.linvel();
                     let angle = if aim {
@@ -399,14 +1038,18 @@ impl Player {
                         .build();
 
                     let trans = rb.position().translation;
+                    let linvel = *rb.linvel();
 
                     let handle = rbs.insert(rb);
 
                     let bullet = Bullet {
                         handle,
+                        owner: self.id,
+                        damage: c.spec.damage(),
                         lifetime: 0.,
                         pos: vec2(trans.x, trans.y),
                         angle,
+                        linvel: vec2(linvel.x, linvel.y),
                     };
 
                     bullets.push(bullet);
@@ -418,7 +1061,7 @@ impl Player {
                 }
                 CS::Cannon { cooldown, aim } => Some(Component {
                     spec: CS::Cannon {
-                        cooldown: cooldown - delta,
+                        cooldown: (cooldown - delta).max(0.),
                         aim,
                     },
                     ..*c
@@ -434,19 +1077,70 @@ impl Player {
             .collect();
     }
 
+    /// Copies positions and velocities from the physics bodies
+    pub fn sync_physics(&mut self, rbs: &RigidBodySet) {
+        for component in &mut self.components {
+            let rb = rbs
+                .get(component.physics_handle)
+                .expect(&format!("Missing physics rigid body for player {}", self.id));
+
+            let pos = rb.position();
+            component.pos = vec2(pos.translation.x, pos.translation.y);
+            component.angle = pos.rotation.angle();
+            component.linvel = vec2(rb.linvel().x, rb.linvel().y);
+            component.angvel = rb.angvel();
+        }
+        self.place_shield_points();
+    }
+
+    /// Puts the shield arc around where the ship is. Clients do the same
+    /// instead of receiving the points
+    pub fn place_shield_points(&mut self) {
+        self.shield.points = if self.has_ship() {
+            self.shield.arc_points(self.position())
+        } else {
+            vec![]
+        };
+    }
+
     pub fn core(&self) -> &Component {
         self.components.first().expect("Player had no components")
     }
 
+    /// Where the root is, the origin for players without a ship
     pub fn position(&self) -> Vec2 {
-        self.core().pos
+        self.components
+            .first()
+            .map(|root| root.pos)
+            .unwrap_or(vec2(0., 0.))
     }
 
     pub fn angle(&self) -> f32 {
-        self.core().angle
+        self.components.first().map(|root| root.angle).unwrap_or(0.)
     }
 
     pub fn velocity(&self) -> f32 {
-        0.0
+        self.components
+            .first()
+            .map(|root| root.linvel.norm())
+            .unwrap_or(0.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placements_outside_the_world_are_rejected() {
+        let valid = vec2(100., 200.);
+        assert_eq!(BuildRejection::check_placement(valid, 0.5), Ok(()));
+
+        let rejected = Err(BuildRejection::InvalidPlacement);
+        for bad in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(BuildRejection::check_placement(valid, bad), rejected);
+            assert_eq!(BuildRejection::check_placement(vec2(bad, 200.), 0.), rejected);
+            assert_eq!(BuildRejection::check_placement(vec2(100., bad), 0.), rejected);
+        }
     }
 }
@@ -0,0 +1,87 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Groups of modules that are powered together. When there is not enough
+/// energy, the systems listed first get it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum PowerSystem {
+    Shield,
+    Steering,
+    Engines,
+    Weapons,
+}
+
+impl PowerSystem {
+    pub const PRIORITY: [PowerSystem; 4] = [
+        PowerSystem::Shield,
+        PowerSystem::Steering,
+        PowerSystem::Engines,
+        PowerSystem::Weapons,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Power {
+    pub energy: f32,
+    pub capacity: f32,
+    // energy per second made by the ship's generators
+    pub generation: f32,
+    // energy per second the ship wanted during the last update
+    pub demand: f32,
+    // systems that wanted power during the last update, split by whether
+    // they got it
+    pub powered: Vec<PowerSystem>,
+    pub browned_out: Vec<PowerSystem>,
+}
+
+impl Power {
+    pub fn new() -> Power {
+        Power {
+            energy: 0.,
+            capacity: 0.,
+            generation: 0.,
+            demand: 0.,
+            powered: vec![],
+            browned_out: vec![],
+        }
+    }
+
+    /// Hands out the stored and generated energy to the systems in order of
+    /// priority. `requests` is the energy each system needs for this update
+    pub fn distribute(&mut self, delta: f32, requests: &[(PowerSystem, f32)]) {
+        let mut budget = self.energy + self.generation * delta;
+        self.demand = 0.;
+        self.powered.clear();
+        self.browned_out.clear();
+
+        for system in PowerSystem::PRIORITY {
+            let request = requests
+                .iter()
+                .filter(|(s, _)| *s == system)
+                .map(|(_, energy)| energy)
+                .sum::<f32>();
+            if request <= 0. {
+                continue;
+            }
+
+            self.demand += request / delta;
+            if request <= budget {
+                budget -= request;
+                self.powered.push(system);
+            } else {
+                self.browned_out.push(system);
+            }
+        }
+
+        self.energy = budget.min(self.capacity);
+    }
+
+    /// Systems run unless they were browned out, so ones that need no
+    /// energy always work
+    pub fn is_powered(&self, system: PowerSystem) -> bool {
+        !self.browned_out.contains(&system)
+    }
+
+    pub fn drain(&mut self, energy: f32) {
+        self.energy = (self.energy - energy).max(0.);
+    }
+}
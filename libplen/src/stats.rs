@@ -0,0 +1,81 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// What a player has done this round. Kept across deaths, cleared when the
+/// next round starts
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Stats {
+    pub kills: u32,
+    pub deaths: u32,
+    pub damage_dealt: f32,
+    pub damage_taken: f32,
+    pub modules_built: u32,
+    pub asteroids_destroyed: u32,
+    // seconds the current ship has been alive
+    pub time_alive: f32,
+    pub longest_life: f32,
+}
+
+/// How much each stat is worth towards a player's score
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct ScoreFormula {
+    pub kills: f32,
+    pub deaths: f32,
+    pub damage_dealt: f32,
+    pub damage_taken: f32,
+    pub modules_built: f32,
+    pub asteroids_destroyed: f32,
+    // per second of the longest life
+    pub longest_life: f32,
+}
+
+impl Default for ScoreFormula {
+    fn default() -> Self {
+        ScoreFormula {
+            kills: 100.,
+            deaths: -50.,
+            damage_dealt: 1.,
+            damage_taken: 0.,
+            modules_built: 5.,
+            asteroids_destroyed: 10.,
+            longest_life: 0.,
+        }
+    }
+}
+
+impl ScoreFormula {
+    pub fn is_finite(&self) -> bool {
+        [
+            self.kills,
+            self.deaths,
+            self.damage_dealt,
+            self.damage_taken,
+            self.modules_built,
+            self.asteroids_destroyed,
+            self.longest_life,
+        ]
+        .iter()
+        .all(|weight| weight.is_finite())
+    }
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    pub fn add_time_alive(&mut self, delta: f32) {
+        self.time_alive += delta;
+        self.longest_life = self.longest_life.max(self.time_alive);
+    }
+
+    pub fn score(&self, formula: &ScoreFormula) -> f32 {
+        self.kills as f32 * formula.kills
+            + self.deaths as f32 * formula.deaths
+            + self.damage_dealt * formula.damage_dealt
+            + self.damage_taken * formula.damage_taken
+            + self.modules_built as f32 * formula.modules_built
+            + self.asteroids_destroyed as f32 * formula.asteroids_destroyed
+            + self.longest_life * formula.longest_life
+    }
+}
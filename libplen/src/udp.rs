@@ -0,0 +1,733 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use macroquad::rand::gen_range;
+
+use crate::messages::{self, Channel, Transport};
+
+// Every datagram starts with a kind byte and a big endian u32 sequence number
+const HELLO: u8 = 0;
+const RELIABLE: u8 = 1;
+const UNRELIABLE: u8 = 2;
+const ACK: u8 = 3;
+// A reliable message that continues in the datagram with the next sequence
+// number
+const RELIABLE_FRAGMENT: u8 = 4;
+// Part of an unreliable message. The payload starts with the index of the
+// fragment and the number of fragments, both big endian u16
+const UNRELIABLE_FRAGMENT: u8 = 5;
+const HEADER_SIZE: usize = 5;
+const FRAGMENT_HEADER_SIZE: usize = 4;
+
+// Largest payload that fits in a single UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65507;
+// Messages bigger than this are split up, so that datagrams fit in common
+// MTUs instead of relying on IP fragmentation
+const FRAGMENT_SIZE: usize = 1200;
+
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+// Reliable datagrams further ahead than this, or that would buffer more than
+// MAX_OUT_OF_ORDER_BYTES behind a lost one, are dropped without an ack. The
+// peer sends them again later, but can't make us hold on to unlimited data
+const RELIABLE_WINDOW: u32 = 1024;
+const MAX_OUT_OF_ORDER_BYTES: usize = 2 * 1024 * 1024;
+
+/// Artificial packet loss and latency applied to everything a transport
+/// sends, for testing on loopback
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LinkConditions {
+    /// Probability in 0..1 that a datagram is dropped
+    pub loss: f32,
+    pub latency: Duration,
+    /// Extra random delay in 0..jitter added on top of the latency
+    pub jitter: Duration,
+}
+
+impl LinkConditions {
+    /// Reads SIMULATE_LOSS (0..1), SIMULATE_LATENCY_MS and
+    /// SIMULATE_JITTER_MS, defaulting to a perfect link
+    pub fn from_env() -> LinkConditions {
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<f32>().ok());
+
+        LinkConditions {
+            loss: var("SIMULATE_LOSS").unwrap_or(0.),
+            latency: Duration::from_secs_f32(var("SIMULATE_LATENCY_MS").unwrap_or(0.) / 1000.),
+            jitter: Duration::from_secs_f32(var("SIMULATE_JITTER_MS").unwrap_or(0.) / 1000.),
+        }
+    }
+
+    fn is_perfect(&self) -> bool {
+        self.loss <= 0. && self.latency.is_zero() && self.jitter.is_zero()
+    }
+}
+
+struct LinkSimulator {
+    conditions: LinkConditions,
+    in_flight: Vec<(Instant, Vec<u8>)>,
+}
+
+impl LinkSimulator {
+    fn new(conditions: LinkConditions) -> Self {
+        Self {
+            conditions,
+            in_flight: vec![],
+        }
+    }
+
+    fn submit(&mut self, datagram: Vec<u8>) {
+        if self.conditions.loss > 0. && gen_range(0., 1.) < self.conditions.loss {
+            return;
+        }
+        let jitter = self.conditions.jitter.mul_f32(gen_range(0., 1.));
+        self.in_flight
+            .push((Instant::now() + self.conditions.latency + jitter, datagram));
+    }
+
+    fn due(&mut self) -> Vec<Vec<u8>> {
+        let now = Instant::now();
+        let (due, waiting) = self
+            .in_flight
+            .drain(..)
+            .partition::<Vec<_>, _>(|(at, _)| *at <= now);
+        self.in_flight = waiting;
+        due.into_iter().map(|(_, datagram)| datagram).collect()
+    }
+}
+
+fn datagram(kind: u8, sequence: u32, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_SIZE + payload.len());
+    datagram.push(kind);
+    datagram.extend_from_slice(&sequence.to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+fn parse_header(datagram: &[u8]) -> Option<(u8, u32, &[u8])> {
+    if datagram.len() < HEADER_SIZE {
+        return None;
+    }
+    let sequence = u32::from_be_bytes([datagram[1], datagram[2], datagram[3], datagram[4]]);
+    Some((datagram[0], sequence, &datagram[HEADER_SIZE..]))
+}
+
+fn is_valid_hello(payload: &[u8]) -> bool {
+    payload == messages::handshake()
+}
+
+/// The pieces a message is sent in, at least one even if it is empty
+fn fragments(message: &[u8]) -> Vec<&[u8]> {
+    if message.is_empty() {
+        return vec![message];
+    }
+    message.chunks(FRAGMENT_SIZE).collect()
+}
+
+/// An unreliable message that has only partly arrived
+struct PartialMessage {
+    sequence: u32,
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+enum Incoming {
+    // Client side, we own the socket
+    Socket,
+    // Server side, the listener reads the shared socket and forwards our
+    // datagrams
+    Listener(mpsc::Receiver<Vec<u8>>),
+}
+
+/// Reliable-ordered and unreliable-sequenced channels over UDP
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    incoming: Incoming,
+    simulator: LinkSimulator,
+
+    connected: bool,
+    last_hello: Option<Instant>,
+    max_frame_size: usize,
+
+    next_reliable: u32,
+    unacked: BTreeMap<u32, (Instant, Vec<u8>)>,
+    next_expected_reliable: u32,
+    // Kind and payload of datagrams that arrived ahead of a lost one
+    out_of_order: BTreeMap<u32, (u8, Vec<u8>)>,
+    out_of_order_bytes: usize,
+    // The start of a reliable message whose last fragment is still missing
+    reliable_fragments: Vec<u8>,
+
+    next_unreliable: u32,
+    newest_unreliable: Option<u32>,
+    unreliable_fragments: Option<PartialMessage>,
+}
+
+impl UdpTransport {
+    fn new(socket: UdpSocket, peer: SocketAddr, incoming: Incoming, connected: bool) -> Self {
+        Self {
+            socket,
+            peer,
+            incoming,
+            simulator: LinkSimulator::new(LinkConditions::default()),
+            connected,
+            last_hello: None,
+            max_frame_size: messages::DEFAULT_MAX_FRAME_SIZE,
+            next_reliable: 0,
+            unacked: BTreeMap::new(),
+            next_expected_reliable: 0,
+            out_of_order: BTreeMap::new(),
+            out_of_order_bytes: 0,
+            reliable_fragments: vec![],
+            next_unreliable: 0,
+            newest_unreliable: None,
+            unreliable_fragments: None,
+        }
+    }
+
+    /// Starts connecting to a server. Messages sent before the server has
+    /// answered are resent like any other reliable message
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let peer = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+        })?;
+        let local: SocketAddr = if peer.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+
+        let mut transport = Self::new(socket, peer, Incoming::Socket, false);
+        transport.send_hello_if_due()?;
+        Ok(transport)
+    }
+
+    pub fn set_link_conditions(&mut self, conditions: LinkConditions) {
+        self.simulator.conditions = conditions;
+    }
+
+    /// Largest message that is sent or put back together from fragments
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    fn send_datagram(&mut self, datagram: Vec<u8>) -> io::Result<()> {
+        if !self.simulator.conditions.is_perfect() {
+            self.simulator.submit(datagram);
+            return Ok(());
+        }
+        self.send_now(&datagram)
+    }
+
+    fn send_now(&self, datagram: &[u8]) -> io::Result<()> {
+        match self.socket.send_to(datagram, self.peer) {
+            // Lost datagrams are either unreliable or will be resent
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn send_hello_if_due(&mut self) -> io::Result<()> {
+        let due = self
+            .last_hello
+            .map(|at| at.elapsed() >= RESEND_INTERVAL)
+            .unwrap_or(true);
+        if !self.connected && due {
+            self.last_hello = Some(Instant::now());
+            self.send_datagram(datagram(HELLO, 0, &messages::handshake()))?;
+        }
+        Ok(())
+    }
+
+    fn resend_unacked(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let due = self
+            .unacked
+            .values_mut()
+            .filter(|(sent, _)| now.duration_since(*sent) >= RESEND_INTERVAL)
+            .map(|(sent, datagram)| {
+                *sent = now;
+                datagram.clone()
+            })
+            .collect::<Vec<_>>();
+
+        for datagram in due {
+            self.send_datagram(datagram)?;
+        }
+        Ok(())
+    }
+
+    /// Holds on to a reliable datagram until the ones before it are in.
+    /// Returns false if it was dropped instead
+    fn buffer_reliable(&mut self, kind: u8, sequence: u32, payload: &[u8]) -> bool {
+        // Already delivered, the peer missed our ack
+        if sequence < self.next_expected_reliable {
+            return true;
+        }
+        let too_far = sequence - self.next_expected_reliable > RELIABLE_WINDOW;
+        // The next one always fits, so that the buffer can drain
+        let too_much = sequence != self.next_expected_reliable
+            && self.out_of_order_bytes + payload.len() > MAX_OUT_OF_ORDER_BYTES;
+        if too_far || too_much {
+            return false;
+        }
+
+        if let Some((_, old)) = self.out_of_order.insert(sequence, (kind, payload.to_vec())) {
+            self.out_of_order_bytes -= old.len();
+        }
+        self.out_of_order_bytes += payload.len();
+        true
+    }
+
+    fn handle_datagram(&mut self, data: &[u8], received: &mut Vec<Vec<u8>>) -> io::Result<()> {
+        let (kind, sequence, payload) = match parse_header(data) {
+            Some(header) => header,
+            None => return Ok(()),
+        };
+
+        match kind {
+            HELLO => {
+                if !is_valid_hello(payload) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "peer does not speak our protocol version",
+                    ));
+                }
+                if let Incoming::Listener(_) = self.incoming {
+                    // Our answer got lost, the client is still saying hello
+                    self.send_datagram(datagram(HELLO, 0, &messages::handshake()))?;
+                }
+                self.connected = true;
+            }
+            RELIABLE | RELIABLE_FRAGMENT => {
+                self.connected = true;
+                if !self.buffer_reliable(kind, sequence, payload) {
+                    return Ok(());
+                }
+                self.send_datagram(datagram(ACK, sequence, &[]))?;
+                while let Some((kind, data)) =
+                    self.out_of_order.remove(&self.next_expected_reliable)
+                {
+                    self.next_expected_reliable += 1;
+                    self.out_of_order_bytes -= data.len();
+                    self.reliable_fragments.extend_from_slice(&data);
+                    if self.reliable_fragments.len() > self.max_frame_size {
+                        return Err(messages::frame_too_large(
+                            self.reliable_fragments.len(),
+                            self.max_frame_size,
+                        ));
+                    }
+                    if kind == RELIABLE {
+                        received.push(std::mem::take(&mut self.reliable_fragments));
+                    }
+                }
+            }
+            UNRELIABLE => {
+                self.connected = true;
+                if self.is_newest_unreliable(sequence) {
+                    self.newest_unreliable = Some(sequence);
+                    received.push(payload.to_vec());
+                }
+            }
+            UNRELIABLE_FRAGMENT => {
+                self.connected = true;
+                if let Some(message) = self.add_unreliable_fragment(sequence, payload) {
+                    self.newest_unreliable = Some(sequence);
+                    received.push(message);
+                }
+            }
+            ACK => {
+                self.unacked.remove(&sequence);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn is_newest_unreliable(&self, sequence: u32) -> bool {
+        self.newest_unreliable.map(|n| sequence > n).unwrap_or(true)
+    }
+
+    /// Returns the message once all of its fragments are in. Only the newest
+    /// message is collected, an older one would be dropped on arrival anyway
+    fn add_unreliable_fragment(&mut self, sequence: u32, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload.len() < FRAGMENT_HEADER_SIZE || !self.is_newest_unreliable(sequence) {
+            return None;
+        }
+        let index = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+        let count = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+        if index >= count || count > self.max_frame_size / FRAGMENT_SIZE + 1 {
+            return None;
+        }
+
+        match &self.unreliable_fragments {
+            Some(partial) if partial.sequence > sequence => return None,
+            Some(partial) if partial.sequence == sequence && partial.fragments.len() == count => {}
+            _ => {
+                self.unreliable_fragments = Some(PartialMessage {
+                    sequence,
+                    fragments: vec![None; count],
+                    missing: count,
+                })
+            }
+        }
+
+        let partial = self.unreliable_fragments.as_mut()?;
+        let fragment = &mut partial.fragments[index];
+        if fragment.is_none() {
+            partial.missing -= 1;
+        }
+        *fragment = Some(payload[FRAGMENT_HEADER_SIZE..].to_vec());
+        if partial.missing > 0 {
+            return None;
+        }
+
+        let partial = self.unreliable_fragments.take()?;
+        Some(partial.fragments.into_iter().flatten().flatten().collect())
+    }
+
+    fn read_datagrams(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut datagrams = vec![];
+        match &self.incoming {
+            Incoming::Socket => {
+                let mut buffer = [0; MAX_DATAGRAM_SIZE];
+                loop {
+                    match self.socket.recv_from(&mut buffer) {
+                        Ok((amount, from)) if from == self.peer => {
+                            datagrams.push(buffer[..amount].to_vec())
+                        }
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Incoming::Listener(receiver) => datagrams.extend(receiver.try_iter()),
+        }
+        Ok(datagrams)
+    }
+}
+
+impl Transport for UdpTransport {
+    /// Messages that don't fit in a datagram are sent in fragments. Losing
+    /// any fragment of an unreliable message loses the whole message
+    fn send(&mut self, channel: Channel, message: &[u8]) -> io::Result<()> {
+        let fragments = fragments(message);
+        if message.len() > self.max_frame_size || fragments.len() > u16::MAX as usize {
+            return Err(messages::frame_too_large(
+                message.len(),
+                self.max_frame_size,
+            ));
+        }
+
+        match channel {
+            Channel::Reliable => {
+                let last = fragments.len() - 1;
+                for (index, fragment) in fragments.into_iter().enumerate() {
+                    let sequence = self.next_reliable;
+                    self.next_reliable += 1;
+
+                    let kind = if index == last {
+                        RELIABLE
+                    } else {
+                        RELIABLE_FRAGMENT
+                    };
+                    let datagram = datagram(kind, sequence, fragment);
+                    self.unacked
+                        .insert(sequence, (Instant::now(), datagram.clone()));
+                    self.send_datagram(datagram)?;
+                }
+                Ok(())
+            }
+            Channel::Unreliable => {
+                let sequence = self.next_unreliable;
+                self.next_unreliable += 1;
+
+                if fragments.len() == 1 {
+                    return self.send_datagram(datagram(UNRELIABLE, sequence, message));
+                }
+                let count = fragments.len() as u16;
+                for (index, fragment) in fragments.into_iter().enumerate() {
+                    let mut payload = Vec::with_capacity(FRAGMENT_HEADER_SIZE + fragment.len());
+                    payload.extend_from_slice(&(index as u16).to_be_bytes());
+                    payload.extend_from_slice(&count.to_be_bytes());
+                    payload.extend_from_slice(fragment);
+                    self.send_datagram(datagram(UNRELIABLE_FRAGMENT, sequence, &payload))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut received = vec![];
+        for data in self.read_datagrams()? {
+            self.handle_datagram(&data, &mut received)?;
+        }
+
+        self.send_hello_if_due()?;
+        self.resend_unacked()?;
+        for datagram in self.simulator.due() {
+            self.send_now(&datagram)?;
+        }
+
+        Ok(received)
+    }
+
+    fn backlog(&self) -> usize {
+        self.unacked
+            .values()
+            .map(|(_, datagram)| datagram.len())
+            .sum()
+    }
+
+    /// Unreliable messages are sent right away and never queued
+    fn discard_unreliable(&mut self) {}
+
+    /// There is no connection to close, the peer stops hearing from us
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Accepts UDP clients on a single socket and hands out a transport per peer
+pub struct UdpListener {
+    socket: UdpSocket,
+    peers: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>,
+    link_conditions: LinkConditions,
+    max_frame_size: usize,
+}
+
+impl UdpListener {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            peers: HashMap::new(),
+            link_conditions: LinkConditions::default(),
+            max_frame_size: messages::DEFAULT_MAX_FRAME_SIZE,
+        })
+    }
+
+    /// Applied to the transports of peers that connect after this call
+    pub fn set_link_conditions(&mut self, conditions: LinkConditions) {
+        self.link_conditions = conditions;
+    }
+
+    /// Applied to the transports of peers that connect after this call
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Routes waiting datagrams to their transports and returns transports
+    /// for peers that said hello for the first time. Socket errors are
+    /// logged, one bad peer should not take the listener down
+    pub fn accept(&mut self) -> Vec<UdpTransport> {
+        let mut new_peers = vec![];
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (amount, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(result) => result,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // A previous send_to failed with ICMP port unreachable on
+                // some platforms, that is the peer's problem
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    println!("Could not receive udp datagram: {}", e);
+                    break;
+                }
+            };
+            let data = buffer[..amount].to_vec();
+
+            if let Some(sender) = self.peers.get(&from) {
+                match sender.send(data) {
+                    Ok(()) => continue,
+                    // The transport was dropped, so this is either a stray
+                    // datagram or the peer reconnecting
+                    Err(mpsc::SendError(data)) => {
+                        self.peers.remove(&from);
+                        new_peers.extend(self.accept_peer(from, &data));
+                    }
+                }
+            } else {
+                new_peers.extend(self.accept_peer(from, &data));
+            }
+        }
+        new_peers
+    }
+
+    fn accept_peer(&mut self, from: SocketAddr, data: &[u8]) -> Option<UdpTransport> {
+        match parse_header(data) {
+            Some((HELLO, _, payload)) if is_valid_hello(payload) => {}
+            Some((HELLO, _, _)) => {
+                // Tell the client which version we have so that it can fail
+                // with a proper error
+                let reply = datagram(HELLO, 0, &messages::handshake());
+                let _ = self.socket.send_to(&reply, from);
+                return None;
+            }
+            _ => return None,
+        }
+
+        let socket = match self.socket.try_clone() {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Could not accept udp peer {}: {}", from, e);
+                return None;
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let mut transport = UdpTransport::new(socket, from, Incoming::Listener(receiver), true);
+        transport.set_link_conditions(self.link_conditions);
+        transport.set_max_frame_size(self.max_frame_size);
+        // The peer keeps saying hello until it hears back, so a lost answer
+        // is sent again from handle_datagram
+        if let Err(e) = transport.send_datagram(datagram(HELLO, 0, &messages::handshake())) {
+            println!("Could not answer udp peer {}: {}", from, e);
+        }
+        self.peers.insert(from, sender);
+        Some(transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn lossy_link() -> LinkConditions {
+        LinkConditions {
+            loss: 0.3,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(20),
+        }
+    }
+
+    /// A listener on loopback and both ends of a connection to it
+    fn connect(conditions: LinkConditions) -> (UdpListener, UdpTransport, UdpTransport) {
+        let mut listener = UdpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_link_conditions(conditions);
+        let mut client = UdpTransport::connect(listener.socket.local_addr().unwrap()).unwrap();
+        client.set_link_conditions(conditions);
+
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < TIMEOUT, "the client never got through");
+            thread::sleep(Duration::from_millis(1));
+            client.receive().unwrap();
+            if let Some(server) = listener.accept().pop() {
+                return (listener, client, server);
+            }
+        }
+    }
+
+    /// Lets both ends do their network work once, returns what the server got
+    fn step(
+        listener: &mut UdpListener,
+        client: &mut UdpTransport,
+        server: &mut UdpTransport,
+    ) -> Vec<Vec<u8>> {
+        thread::sleep(Duration::from_millis(1));
+        assert!(listener.accept().is_empty());
+        client.receive().unwrap();
+        server.receive().unwrap()
+    }
+
+    #[test]
+    fn reliable_messages_arrive_in_order() {
+        let (mut listener, mut client, mut server) = connect(lossy_link());
+
+        // Up to a few fragments each, and an empty one
+        let messages = (0..50).map(|i| vec![i as u8; i * 100]).collect::<Vec<_>>();
+        for message in &messages {
+            client.send(Channel::Reliable, message).unwrap();
+        }
+
+        let mut received = vec![];
+        let start = Instant::now();
+        while received.len() < messages.len() || client.backlog() > 0 {
+            assert!(start.elapsed() < TIMEOUT, "got {} messages", received.len());
+            received.extend(step(&mut listener, &mut client, &mut server));
+        }
+        assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn unreliable_messages_arrive_newest_first() {
+        let (mut listener, mut client, mut server) = connect(lossy_link());
+
+        let mut received = vec![];
+        for i in 0..200u32 {
+            let mut message = i.to_be_bytes().to_vec();
+            // Some need fragments, and are lost if any of them is
+            if i % 10 == 0 {
+                message.resize(3 * FRAGMENT_SIZE, 0);
+            }
+            client.send(Channel::Unreliable, &message).unwrap();
+            received.extend(step(&mut listener, &mut client, &mut server));
+        }
+        // Let the delayed datagrams arrive
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(100) {
+            received.extend(step(&mut listener, &mut client, &mut server));
+        }
+
+        let sequences = received
+            .iter()
+            .map(|message| u32::from_be_bytes([message[0], message[1], message[2], message[3]]))
+            .collect::<Vec<_>>();
+        assert!(!sequences.is_empty());
+        assert!(
+            sequences.windows(2).all(|pair| pair[0] < pair[1]),
+            "stale messages were delivered: {:?}",
+            sequences
+        );
+    }
+
+    #[test]
+    fn late_unreliable_messages_are_dropped() {
+        let (_listener, mut client, _server) = connect(LinkConditions::default());
+
+        let mut received = vec![];
+        for sequence in [1, 0, 3, 2, 4] {
+            let datagram = datagram(UNRELIABLE, sequence, &[sequence as u8]);
+            client.handle_datagram(&datagram, &mut received).unwrap();
+        }
+        assert_eq!(received, vec![vec![1], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn reliable_buffering_is_bounded() {
+        let (_listener, mut client, _server) = connect(LinkConditions::default());
+
+        let mut received = vec![];
+        let far_ahead = datagram(RELIABLE, RELIABLE_WINDOW + 1, &[1]);
+        client.handle_datagram(&far_ahead, &mut received).unwrap();
+        assert!(client.out_of_order.is_empty());
+
+        // Sequence 0 is missing, so everything after it waits
+        let payload = vec![0; MAX_DATAGRAM_SIZE - HEADER_SIZE];
+        for sequence in 1..RELIABLE_WINDOW {
+            let datagram = datagram(RELIABLE, sequence, &payload);
+            client.handle_datagram(&datagram, &mut received).unwrap();
+        }
+        assert!(client.out_of_order_bytes <= MAX_OUT_OF_ORDER_BYTES);
+
+        // The missing one still gets through and lets the rest out
+        let missing = datagram(RELIABLE, 0, &[]);
+        client.handle_datagram(&missing, &mut received).unwrap();
+        assert_eq!(received.len(), client.next_expected_reliable as usize);
+        assert!(received.len() > 1);
+        assert!(client.out_of_order.is_empty());
+        assert_eq!(client.out_of_order_bytes, 0);
+    }
+}
@@ -4,6 +4,7 @@ use anyhow::Result;
 use egui_extras::image::RetainedImage;
 use egui_macroquad::egui;
 
+use libplen::player::ComponentSpecialization;
 use macroquad::texture::*;
 
 fn load_image_from_path(bytes: &[u8]) -> Result<egui::ColorImage, image::ImageError> {
@@ -19,6 +20,29 @@ fn load_image_from_path(bytes: &[u8]) -> Result<egui::ColorImage, image::ImageEr
 
 pub struct EguiTextures {
     pub cannon: RetainedImage,
+    pub shield: RetainedImage,
+    pub thrusters: RetainedImage,
+    pub reaction_wheel: RetainedImage,
+    pub generator: RetainedImage,
+    pub battery: RetainedImage,
+    pub mining_laser: RetainedImage,
+}
+
+impl EguiTextures {
+    /// Icon for the build palette. Turrets and fixed cannons share the
+    /// cannon sprite and are told apart by their labels
+    pub fn module(&self, spec: &ComponentSpecialization) -> &RetainedImage {
+        use ComponentSpecialization as CS;
+        match spec {
+            CS::Root | CS::Generator => &self.generator,
+            CS::Shield => &self.shield,
+            CS::Thrusters => &self.thrusters,
+            CS::Reactionwheel { .. } => &self.reaction_wheel,
+            CS::Cannon { .. } => &self.cannon,
+            CS::Battery => &self.battery,
+            CS::MiningLaser => &self.mining_laser,
+        }
+    }
 }
 
 pub struct Stars {
@@ -39,6 +63,9 @@ pub struct Assets {
     pub reaction_wheel_bot: Texture2D,
     pub reaction_wheel_mid: Texture2D,
     pub reaction_wheel_top: Texture2D,
+    pub generator: Texture2D,
+    pub battery: Texture2D,
+    pub mining_laser: Texture2D,
 }
 
 impl Stars {
@@ -54,6 +81,15 @@ impl Stars {
     }
 }
 
+macro_rules! load_egui_image {
+    ($path:expr) => {{
+        RetainedImage::from_color_image(
+            "egui",
+            load_image_from_path(include_bytes!($path)).unwrap(),
+        )
+    }};
+}
+
 macro_rules! load_pixelart {
     ($path:expr) => {{
         let result = Texture2D::from_file_with_format(include_bytes!($path), None);
@@ -73,17 +109,40 @@ impl Assets {
             thrust_flame: load_pixelart!("../resources/ship/thruster.png"),
             stars: Stars::new(),
             egui_textures: EguiTextures {
-                cannon: RetainedImage::from_color_image(
-                    "egui",
-                    load_image_from_path(include_bytes!("../resources/cannon1.png")).unwrap(),
-                ),
+                cannon: load_egui_image!("../resources/cannon1.png"),
+                shield: load_egui_image!("../resources/ship/shield.png"),
+                thrusters: load_egui_image!("../resources/ship/thrusters.png"),
+                reaction_wheel: load_egui_image!("../resources/ship/reaction_wheel_top.png"),
+                generator: load_egui_image!("../resources/ship/root.png"),
+                battery: load_egui_image!("../resources/ship/frame.png"),
+                mining_laser: load_egui_image!("../resources/ship/laser.png"),
             },
             bullet: load_pixelart!("../resources/ship/laser.png"),
             thrusters: load_pixelart!("../resources/ship/thrusters.png"),
             reaction_wheel_bot: load_pixelart!("../resources/ship/reaction_wheel_bottom.png"),
             reaction_wheel_mid: load_pixelart!("../resources/ship/reaction_wheel_wheel.png"),
             reaction_wheel_top: load_pixelart!("../resources/ship/reaction_wheel_top.png"),
+            // Generators and batteries have no art of their own yet, they
+            // borrow the root and the bare frame
+            generator: load_pixelart!("../resources/ship/root.png"),
+            battery: load_pixelart!("../resources/ship/frame.png"),
+            mining_laser: load_pixelart!("../resources/ship/laser.png"),
         };
         Ok(assets)
     }
+
+    /// The sprite that shows what a module is, without its moving parts
+    pub fn module_icon(&self, spec: &ComponentSpecialization) -> Texture2D {
+        use ComponentSpecialization as CS;
+        match spec {
+            CS::Root => self.root_node,
+            CS::Shield => self.shield,
+            CS::Thrusters => self.thrusters,
+            CS::Reactionwheel { .. } => self.reaction_wheel_top,
+            CS::Cannon { .. } => self.cannon,
+            CS::Generator => self.generator,
+            CS::Battery => self.battery,
+            CS::MiningLaser => self.mining_laser,
+        }
+    }
 }
@@ -1,32 +1,101 @@
 mod assets;
 mod client_state;
+mod interpolation;
+mod prediction;
 mod rendering;
 
-use std::io::prelude::*;
+use std::collections::VecDeque;
+use std::io;
 use std::net::TcpStream;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use client_state::ClientState;
 use egui::{Align, Layout, Sense};
 use egui_macroquad::egui::{self, Color32, Painter, Rounding, Stroke, Ui};
 
 use assets::Assets;
-use libplen::constants::WORLD_SIZE;
+use interpolation::Interpolator;
+use libplen::config::GameConfig;
+use libplen::constants;
 use libplen::gamestate;
-use libplen::messages::{ClientInput, ClientMessage, MessageReader, ServerMessage};
+use libplen::messages::{
+    ClientInput, ClientMessage, ServerMessage, TcpTransport, Transport, DEFAULT_MAX_FRAME_SIZE,
+};
+use libplen::player::ComponentSpecialization;
+use libplen::udp::{LinkConditions, UdpTransport};
+use prediction::Predictor;
 
 use macroquad::prelude::*;
 
-fn send_client_message(msg: &ClientMessage, stream: &mut TcpStream) {
+fn send_client_message(msg: &ClientMessage, transport: &mut dyn Transport) -> io::Result<()> {
     let data = bincode::serialize(msg).expect("Failed to encode message");
-    let length = data.len() as u16;
-    stream
-        .write(&length.to_be_bytes())
-        .expect("Failed to send message length to server");
-    stream
-        .write(&data)
-        .expect("Failed to send message to server");
+    transport.send(msg.channel(), &data)
+}
+
+/// The server dropped us on purpose, so there is no point in reconnecting
+#[derive(Debug)]
+struct Kicked {
+    reason: String,
+}
+
+impl std::fmt::Display for Kicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Kicked by the server: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Kicked {}
+
+/// A connection that the server has assigned an id to
+struct Connection {
+    transport: Box<dyn Transport>,
+    id: u64,
+    token: u64,
+    config: GameConfig,
+    // Whatever arrived together with the id
+    received: Vec<Vec<u8>>,
+}
+
+/// Connects to the server and waits for it to assign us an id
+fn connect(host: &str, use_udp: bool, max_frame_size: usize) -> Result<Connection> {
+    let mut transport: Box<dyn Transport> = if use_udp {
+        let mut transport = UdpTransport::connect(host)?;
+        transport.set_link_conditions(LinkConditions::from_env());
+        transport.set_max_frame_size(max_frame_size);
+        Box::new(transport)
+    } else {
+        let mut transport = TcpTransport::new(TcpStream::connect(host)?)?;
+        transport.set_max_frame_size(max_frame_size);
+        Box::new(transport)
+    };
+    println!("Connected to server");
+
+    let start = Instant::now();
+    let mut received = VecDeque::new();
+    let msg = loop {
+        received.extend(transport.receive()?);
+        if let Some(msg) = received.pop_front() {
+            break bincode::deserialize(&msg)?;
+        }
+        if start.elapsed().as_secs_f32() > constants::CONNECT_TIMEOUT {
+            bail!("Timed out waiting for an id from the server");
+        }
+        std::thread::sleep(Duration::from_secs_f32(constants::CONNECT_POLL_INTERVAL));
+    };
+
+    let ServerMessage::AssignId { id, token, config } = msg else {
+        bail!("Expected to get an id from server");
+    };
+    println!("Received the id {}", id);
+
+    Ok(Connection {
+        transport,
+        id,
+        token,
+        config,
+        received: received.into(),
+    })
 }
 
 #[allow(unused)]
@@ -38,18 +107,69 @@ enum StateResult {
 
 struct MainState {
     my_id: u64,
+    // Proves to the server that we own the session when reconnecting
+    token: u64,
+    // The world the server runs
+    config: GameConfig,
     game_state: gamestate::GameState,
+    // Received snapshots that the server may use as delta baselines
+    snapshots: VecDeque<gamestate::GameState>,
+    // Snapshots that never arrived, judging by gaps in the ticks
+    dropped_snapshots: u64,
     client_state: client_state::ClientState,
+    interpolator: Interpolator,
+    predictor: Predictor,
+    // Sequence number of the input the server last simulated, and for how
+    // many of its ticks
+    last_input: u32,
+    input_ticks: u32,
+    // Smoothed round trip time in seconds
+    rtt: Option<f64>,
+    // Estimated server time minus client time
+    clock_offset: f64,
+    last_ping: Instant,
+    last_heartbeat: Instant,
+    // When the last message from the server arrived
+    last_seen: Instant,
+    // The connection is considered dead after this long without messages
+    connection_timeout: f32,
+    start_time: Instant,
     last_time: Instant,
 }
 
 impl MainState {
-    fn new(my_id: u64) -> MainState {
+    fn new(my_id: u64, token: u64, config: GameConfig) -> MainState {
+        let interpolation_delay = std::env::var("INTERPOLATION_DELAY_MS")
+            .ok()
+            .and_then(|delay| delay.parse::<f32>().ok())
+            .map(|delay| delay / 1000.)
+            .unwrap_or(constants::DEFAULT_INTERPOLATION_DELAY);
+
+        let connection_timeout = std::env::var("CONNECTION_TIMEOUT")
+            .ok()
+            .and_then(|timeout| timeout.parse().ok())
+            .unwrap_or(constants::CONNECTION_TIMEOUT);
+
         MainState {
             my_id,
-            game_state: gamestate::GameState::new(None),
-            client_state: client_state::ClientState::new(my_id),
+            token,
+            game_state: gamestate::GameState::new(None, &config),
+            snapshots: VecDeque::new(),
+            dropped_snapshots: 0,
+            client_state: client_state::ClientState::new(my_id, config.clone()),
+            interpolator: Interpolator::new(interpolation_delay),
+            predictor: Predictor::new(config.clone()),
+            last_input: 0,
+            input_ticks: 0,
+            rtt: None,
+            clock_offset: 0.,
+            last_ping: Instant::now(),
+            last_heartbeat: Instant::now(),
+            last_seen: Instant::now(),
+            connection_timeout,
+            start_time: Instant::now(),
             last_time: Instant::now(),
+            config,
         }
     }
 
@@ -90,6 +210,8 @@ impl MainState {
             .map(|p| ClientState::mouse_world_pos(p));
 
         ClientInput {
+            sequence: 0,
+            ticks: 0,
             x_input,
             y_input,
             mouse_x,
@@ -100,14 +222,15 @@ impl MainState {
             mouse_right,
             shielding,
             aim_angle,
+            flight_assist: self.client_state.flight_assist,
         }
     }
 
     fn update(
         &mut self,
-        server_reader: &mut MessageReader,
+        transport: &mut dyn Transport,
         extra_messages: &mut Vec<ClientMessage>,
-    ) -> StateResult {
+    ) -> Result<StateResult> {
         let elapsed = self.last_time.elapsed();
         self.last_time = Instant::now();
         let dt_duration = std::time::Duration::from_millis(1000 / 60);
@@ -115,16 +238,57 @@ impl MainState {
             std::thread::sleep(dt_duration - elapsed);
         }
 
-        server_reader.fetch_bytes().unwrap();
+        if self.last_ping.elapsed().as_secs_f32() >= constants::PING_INTERVAL {
+            self.last_ping = Instant::now();
+            let ping = ClientMessage::Ping(self.start_time.elapsed().as_secs_f64());
+            send_client_message(&ping, transport)?;
+        }
 
-        for message in server_reader.iter() {
-            match bincode::deserialize(&message).unwrap() {
-                ServerMessage::AssignId(_) => panic!("Got new ID after intialisation"),
-                ServerMessage::GameState(state) => self.game_state = state,
-            }
+        if self.last_heartbeat.elapsed().as_secs_f32() >= constants::HEARTBEAT_INTERVAL {
+            self.last_heartbeat = Instant::now();
+            send_client_message(&ClientMessage::Heartbeat, transport)?;
         }
 
-        let input = self.read_input();
+        let messages = transport.receive()?;
+        if !messages.is_empty() {
+            self.last_seen = Instant::now();
+        } else if self.last_seen.elapsed().as_secs_f32() > self.connection_timeout {
+            bail!(
+                "No messages from the server in {:.0} seconds",
+                self.connection_timeout
+            );
+        }
+
+        let last_snapshot = self.snapshots.back().map(|state| state.tick);
+        self.handle_server_messages(messages)?;
+
+        let new_snapshot = self.snapshots.back().map(|state| state.tick);
+        if let (Some(tick), true) = (new_snapshot, new_snapshot != last_snapshot) {
+            send_client_message(&ClientMessage::AckSnapshot(tick), transport)?;
+
+            let server_player = self
+                .snapshots
+                .back()
+                .and_then(|state| state.players.iter().find(|p| p.id == self.my_id));
+            self.predictor
+                .reconcile(server_player, self.last_input, self.input_ticks);
+        }
+
+        if let Some(state) = self.interpolator.advance(elapsed.as_secs_f32()) {
+            self.game_state = state;
+        }
+
+        let mut input = self.read_input();
+        input.sequence = self.predictor.next_sequence();
+        self.predictor.predict(&mut input, elapsed.as_secs_f32());
+
+        if let Some(predicted) = self.predictor.predicted() {
+            for player in &mut self.game_state.players {
+                if player.id == self.my_id {
+                    *player = predicted.clone();
+                }
+            }
+        }
 
         self.client_state.update(
             elapsed.as_secs_f32(),
@@ -134,9 +298,88 @@ impl MainState {
         );
 
         let input_message = ClientMessage::Input(input);
-        send_client_message(&input_message, &mut server_reader.stream);
+        send_client_message(&input_message, transport)?;
 
-        StateResult::Continue
+        Ok(StateResult::Continue)
+    }
+
+    fn handle_server_messages(&mut self, messages: Vec<Vec<u8>>) -> Result<()> {
+        for message in messages {
+            match bincode::deserialize(&message)? {
+                // The server gave us back the session we had before
+                // reconnecting
+                ServerMessage::AssignId { id, token, .. } => {
+                    println!("Resumed the session of {}", id);
+                    self.my_id = id;
+                    self.token = token;
+                    self.client_state.my_id = id;
+                }
+                ServerMessage::GameState {
+                    state,
+                    last_input,
+                    input_ticks,
+                } => {
+                    self.receive_snapshot(state, last_input, input_ticks);
+                }
+                ServerMessage::GameStateDelta {
+                    delta,
+                    last_input,
+                    input_ticks,
+                } => {
+                    let baseline = self
+                        .snapshots
+                        .iter()
+                        .find(|state| state.tick == delta.baseline)
+                        .map(|state| delta.apply(state, self.config.delta_time()));
+
+                    // Without the baseline we have to wait for the server to
+                    // send a full state again
+                    if let Some(state) = baseline {
+                        // The server never goes back to older baselines
+                        self.snapshots.retain(|state| state.tick >= delta.baseline);
+                        self.receive_snapshot(state, last_input, input_ticks);
+                    }
+                }
+                ServerMessage::Heartbeat => {}
+                ServerMessage::Kicked { reason } => bail!(Kicked { reason }),
+                ServerMessage::BuildRejected { reason } => {
+                    println!("Could not build: {}", reason);
+                    self.client_state.build_rejected(reason);
+                }
+                ServerMessage::Pong {
+                    client_time,
+                    server_time,
+                } => {
+                    let now = self.start_time.elapsed().as_secs_f64();
+                    let rtt = now - client_time;
+                    self.rtt = Some(match self.rtt {
+                        Some(old) => old * 0.8 + rtt * 0.2,
+                        None => rtt,
+                    });
+                    self.clock_offset = server_time + rtt / 2. - now;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_snapshot(&mut self, state: gamestate::GameState, last_input: u32, input_ticks: u32) {
+        if let Some(latest) = self.snapshots.back() {
+            // Unreliable messages may arrive out of order
+            if state.tick <= latest.tick {
+                return;
+            }
+            self.dropped_snapshots += state.tick - latest.tick - 1;
+        }
+
+        self.last_input = last_input;
+        self.input_ticks = input_ticks;
+        self.client_state.add_hits(&state.hits);
+        self.interpolator.push(state.clone());
+        self.snapshots.push_back(state);
+        if self.snapshots.len() > constants::SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
     }
 
     fn draw(&mut self, assets: &mut Assets) -> Result<()> {
@@ -157,8 +400,9 @@ impl MainState {
         y: f32,
         color: Color32,
     ) {
-        let px = inner.min.x + (inner.width() * (x / WORLD_SIZE));
-        let py = inner.min.y + (inner.height() * (y / WORLD_SIZE));
+        let world_size = self.config.world_size;
+        let px = inner.min.x + (inner.width() * (x / world_size));
+        let py = inner.min.y + (inner.height() * (y / world_size));
         painter.rect_filled(
             egui_macroquad::egui::Rect::from_center_size((px, py).into(), (3., 3.).into()),
             Rounding::none(),
@@ -176,14 +420,18 @@ impl MainState {
                 inner,
                 component.pos.x,
                 component.pos.y,
-                Color32::RED,
+                Color32::YELLOW,
             );
         }
     }
 
     fn draw_minimap_asteroids(&self, painter: &mut Painter, inner: &egui_macroquad::egui::Rect) {
+        let world_size = self.config.world_size;
         for asteroid in &self.game_state.asteroids {
-            self.draw_minimap_player(painter, inner, asteroid.x, asteroid.y, Color32::GREEN);
+            let px = inner.min.x + (inner.width() * (asteroid.x / world_size));
+            let py = inner.min.y + (inner.height() * (asteroid.y / world_size));
+            let radius = inner.width() * asteroid.size / world_size / 2.;
+            painter.circle_filled((px, py).into(), radius.max(1.5), Color32::GREEN);
         }
     }
 
@@ -192,18 +440,64 @@ impl MainState {
             if player.id == self.my_id {
                 continue;
             }
+            let color = egui_color(rendering::team_color(player.team));
             for component in &player.components {
-                self.draw_minimap_player(
-                    painter,
-                    inner,
-                    component.pos.x,
-                    component.pos.y,
-                    Color32::WHITE,
-                );
+                self.draw_minimap_player(painter, inner, component.pos.x, component.pos.y, color);
             }
         }
     }
 
+    /// Everyone's stats, best score first
+    pub fn draw_scoreboard(&self, ui: &mut Ui) {
+        let formula = &self.config.score;
+        let mut players = self.game_state.players.iter().collect::<Vec<_>>();
+        players.sort_by(|a, b| b.stats.score(formula).total_cmp(&a.stats.score(formula)));
+
+        if self.game_state.mode.kind.has_teams() {
+            ui.label("Press T to switch teams");
+        }
+        egui::Grid::new("scoreboard").striped(true).show(ui, |ui| {
+            for header in [
+                "name",
+                "score",
+                "kills",
+                "deaths",
+                "damage dealt",
+                "damage taken",
+                "modules built",
+                "asteroids",
+                "longest life",
+            ] {
+                ui.strong(header);
+            }
+            ui.end_row();
+
+            for player in players {
+                let stats = &player.stats;
+                let name = egui::RichText::new(&player.name);
+                let name = match player.team {
+                    Some(team) => name.color(egui_color(rendering::team_color(Some(team)))),
+                    None if player.id == self.my_id => name.color(Color32::YELLOW),
+                    None => name,
+                };
+                if player.id == self.my_id {
+                    ui.label(name.strong());
+                } else {
+                    ui.label(name);
+                }
+                ui.label(format!("{:.0}", stats.score(formula)));
+                ui.label(stats.kills.to_string());
+                ui.label(stats.deaths.to_string());
+                ui.label(format!("{:.0}", stats.damage_dealt));
+                ui.label(format!("{:.0}", stats.damage_taken));
+                ui.label(stats.modules_built.to_string());
+                ui.label(stats.asteroids_destroyed.to_string());
+                ui.label(format!("{:.0}s", stats.longest_life));
+                ui.end_row();
+            }
+        });
+    }
+
     pub fn draw_minimap(&self, ui: &mut Ui) {
         let (response, mut painter) =
             ui.allocate_painter(ui.available_size_before_wrap(), Sense::hover());
@@ -223,44 +517,87 @@ impl MainState {
     }
 }
 
+fn egui_color(color: Color) -> Color32 {
+    let [r, g, b, a]: [u8; 4] = color.into();
+    Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+fn draw_connection_lost(reason: &str, attempts: u32, wait: f32) {
+    let status = if wait > 0. {
+        format!(
+            "Reconnecting in {:.0}s... (attempt {})",
+            wait.ceil(),
+            attempts + 1
+        )
+    } else {
+        format!("Reconnecting... (attempt {})", attempts + 1)
+    };
+    draw_notice(&["Connection lost".to_string(), reason.to_string(), status]);
+}
+
+fn draw_kicked(kicked: &Kicked) {
+    draw_notice(&["Kicked".to_string(), kicked.reason.clone()]);
+}
+
+/// Centered lines on a black screen, the first one as a title
+fn draw_notice(lines: &[String]) {
+    clear_background(BLACK);
+
+    for (i, line) in lines.iter().enumerate() {
+        let size = if i == 0 { 48. } else { 24. };
+        let dimensions = measure_text(line, None, size as u16, 1.);
+        draw_text(
+            line,
+            screen_width() / 2. - dimensions.width / 2.,
+            screen_height() / 2. + i as f32 * 40.,
+            size,
+            WHITE,
+        );
+    }
+}
+
 #[macroquad::main("BasicShapes")]
 async fn main() -> Result<()> {
     let host = std::env::var("SERVER").unwrap_or(String::from("localhost:4444"));
-    let stream = TcpStream::connect(host).expect("Could not connect to server");
-    println!("Connected to server");
-
-    stream
-        .set_nonblocking(true)
-        .expect("Could not set socket as nonblocking");
-    let mut reader = MessageReader::new(stream);
-
-    let msg = loop {
-        reader.fetch_bytes().unwrap();
-        if let Some(msg) = reader.iter().next() {
-            break bincode::deserialize(&msg).unwrap();
-        }
-    };
+    // UDP avoids head-of-line blocking, TCP is there for networks that
+    // block it
+    let use_udp = std::env::var("TRANSPORT").as_deref() == Ok("udp");
+    // Largest message we accept from the server
+    let max_frame_size = std::env::var("MAX_FRAME_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FRAME_SIZE);
+    let connection = connect(&host, use_udp, max_frame_size).expect("Could not connect to server");
+    let mut transport = connection.transport;
 
     let mut assets = assets::Assets::new()?;
 
-    let my_id = if let ServerMessage::AssignId(id) = msg {
-        println!("Received the id {}", id);
-        id
-    } else {
-        panic!("Expected to get an id from server")
-    };
-
-    let mut main_state = MainState::new(my_id);
+    let mut main_state = MainState::new(connection.id, connection.token, connection.config);
+    main_state.handle_server_messages(connection.received)?;
 
     let name = String::new();
+    // The session to reclaim after a reconnect
+    let mut resume = None;
+
+    prevent_quit();
 
     loop {
-        send_client_message(&ClientMessage::JoinGame { name }, &mut reader.stream);
+        let join = match resume {
+            Some((id, token)) => ClientMessage::Resume {
+                id,
+                token,
+                name: name.clone(),
+            },
+            None => ClientMessage::JoinGame { name: name.clone() },
+        };
+        send_client_message(&join, &mut *transport)?;
 
         // let main_state = &mut MainState::new(my_id);
-        loop {
+        let error: anyhow::Error = 'frames: loop {
             let mut client_messages = vec![];
-            main_state.update(&mut reader, &mut client_messages);
+            if let Err(e) = main_state.update(&mut *transport, &mut client_messages) {
+                break e;
+            }
 
             main_state.draw(&mut assets)?;
 
@@ -281,17 +618,45 @@ async fn main() -> Result<()> {
                                 ui.add_space(3.0);
 
                                 ui.with_layout(Layout::left_to_right(Align::LEFT), |ui| {
-                                    ui.image(
-                                        assets.egui_textures.cannon.texture_id(ctx),
-                                        egui::Vec2 { x: 64., y: 64. },
-                                    )
-                                    .interact(egui::Sense {
-                                        click: true,
-                                        drag: true,
-                                        focusable: true,
-                                    })
-                                    .clicked()
-                                    .then(|| println!("Clicked"));
+                                    let client_state = &mut main_state.client_state;
+                                    let modules = ComponentSpecialization::addable();
+                                    for (index, module) in modules.iter().enumerate() {
+                                        ui.vertical(|ui| {
+                                            let response = ui
+                                                .image(
+                                                    assets
+                                                        .egui_textures
+                                                        .module(module)
+                                                        .texture_id(ctx),
+                                                    egui::Vec2 { x: 64., y: 64. },
+                                                )
+                                                .interact(Sense::click_and_drag())
+                                                .on_hover_text(module.name());
+                                            if client_state.is_building
+                                                && client_state.selected_module == index
+                                            {
+                                                ui.painter().rect_stroke(
+                                                    response.rect,
+                                                    Rounding::none(),
+                                                    Stroke::new(2., Color32::LIGHT_BLUE),
+                                                );
+                                            }
+                                            ui.label(format!("{} {}", index + 1, module.name()));
+                                            ui.small(format!("{:.0} ore", module.cost()));
+
+                                            if response.clicked() || response.drag_started() {
+                                                client_state.select_module(index);
+                                            }
+                                            if response.drag_started() {
+                                                client_state.dragging_module = true;
+                                            }
+                                            // Dropped in the world, where it is built
+                                            if response.drag_released() {
+                                                client_state.dragging_module = false;
+                                                client_state.dropped_module = true;
+                                            }
+                                        });
+                                    }
                                 });
                             });
                         },
@@ -300,7 +665,60 @@ async fn main() -> Result<()> {
                 egui::Window::new("minimap").show(ctx, |ui| {
                     main_state.draw_minimap(ui);
                 });
+                if let Some(player) = main_state
+                    .client_state
+                    .my_player(main_state.my_id, &main_state.game_state)
+                {
+                    let power = &player.power;
+                    egui::Window::new("ship").show(ctx, |ui| {
+                        ui.monospace(format!("ore: {:.0}", player.ore));
+                        ui.add(
+                            egui::ProgressBar::new(power.energy / power.capacity.max(1.))
+                                .text(format!("energy {:.0}/{:.0}", power.energy, power.capacity)),
+                        );
+                        ui.monospace(format!(
+                            "power: +{:.1}/s, -{:.1}/s",
+                            power.generation, power.demand
+                        ));
+                        ui.monospace(format!(
+                            "flight assist (F): {}",
+                            if player.flight_assist { "on" } else { "off" }
+                        ));
+                        if !power.browned_out.is_empty() {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                format!("brownout: {:?}", power.browned_out),
+                            );
+                        }
+                    });
+                }
+                if main_state.client_state.show_scoreboard {
+                    egui::Window::new("scoreboard")
+                        .anchor(egui::Align2::CENTER_TOP, (0., 50.))
+                        .collapsible(false)
+                        .resizable(false)
+                        .show(ctx, |ui| main_state.draw_scoreboard(ui));
+                }
                 egui::Window::new("debug").show(ctx, |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut main_state.interpolator.delay, 0.0..=0.5)
+                            .text("interpolation delay"),
+                    );
+                    ui.monospace(format!(
+                        "rtt: {}",
+                        main_state
+                            .rtt
+                            .map(|rtt| format!("{:.1} ms", rtt * 1000.))
+                            .unwrap_or("-".into())
+                    ));
+                    ui.monospace(format!(
+                        "clock offset: {:.1} ms",
+                        main_state.clock_offset * 1000.
+                    ));
+                    ui.monospace(format!(
+                        "server tick: {}, dropped: {}",
+                        main_state.game_state.tick, main_state.dropped_snapshots
+                    ));
                     let Some(player) = main_state
                         .client_state
                         .my_player(main_state.my_id, &main_state.game_state) else { return; };
@@ -320,15 +738,69 @@ async fn main() -> Result<()> {
                         player.mouse_x, player.mouse_y
                     ));
                 });
+
+                main_state.client_state.pointer_over_ui = ctx.is_pointer_over_area();
             });
 
             egui_macroquad::draw();
 
             next_frame().await;
 
+            if is_quit_requested() {
+                // The server keeps our ship around if this does not arrive
+                let _ = send_client_message(&ClientMessage::Leave, &mut *transport);
+                return Ok(());
+            }
+
             while let Some(msg) = client_messages.pop() {
-                send_client_message(&msg, &mut reader.stream);
+                if let Err(e) = send_client_message(&msg, &mut *transport) {
+                    break 'frames e.into();
+                }
+            }
+        };
+
+        if let Some(kicked) = error.downcast_ref::<Kicked>() {
+            println!("{}", kicked);
+            // Dropping the connection lets the server close it right away
+            drop(transport);
+            loop {
+                draw_kicked(kicked);
+                next_frame().await;
+                if is_quit_requested() {
+                    return Ok(());
+                }
             }
         }
+
+        println!("Lost connection to server: {}", error);
+        resume = Some((main_state.my_id, main_state.token));
+
+        let mut attempts = 0;
+        // Don't hammer a server that is down or restarting
+        let mut delay = constants::RECONNECT_DELAY;
+        let mut next_attempt = Instant::now();
+        let connection = loop {
+            let wait = next_attempt.saturating_duration_since(Instant::now());
+            draw_connection_lost(&error.to_string(), attempts, wait.as_secs_f32());
+            next_frame().await;
+
+            if is_quit_requested() {
+                return Ok(());
+            }
+            if Instant::now() < next_attempt {
+                continue;
+            }
+
+            attempts += 1;
+            match connect(&host, use_udp, max_frame_size) {
+                Ok(connection) => break connection,
+                Err(e) => println!("Could not reconnect: {}", e),
+            }
+            next_attempt = Instant::now() + Duration::from_secs_f32(delay);
+            delay = (delay * 2.).min(constants::MAX_RECONNECT_DELAY);
+        };
+        transport = connection.transport;
+        main_state = MainState::new(connection.id, connection.token, connection.config);
+        main_state.handle_server_messages(connection.received)?;
     }
 }
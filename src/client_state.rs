@@ -3,13 +3,15 @@ use std::f32::consts::PI;
 use anyhow::Result;
 use egui_macroquad::egui::emath::exponential_smooth_factor;
 use egui_macroquad::egui::{Color32, Rounding, Sense, Ui};
-use libplen::constants::{ASTEROID_SIZE, WORLD_SIZE};
-use libplen::gamestate::GameState;
+use libplen::config::GameConfig;
+use libplen::gamemode::{GameMode, GameModeKind, Phase, Winner};
+use libplen::gamestate::{GameState, Hit, HitTarget};
+use libplen::killfeed::KillCause;
 use libplen::messages::ClientMessage;
-use libplen::player::{ComponentSpecialization, Player};
+use libplen::player::{BuildRejection, ComponentSpecialization, Player};
 use libplen::{constants, math};
 use macroquad::prelude::*;
-use macroquad::rand::{gen_range, ChooseRandom};
+use macroquad::rand::gen_range;
 
 use crate::assets::Assets;
 
@@ -23,16 +25,34 @@ pub struct Star {
 
 pub struct ClientState {
     pub my_id: u64,
+    config: GameConfig,
     stars: Vec<Star>,
     stars_material: Material,
     pub is_building: bool,
+    // index into ComponentSpecialization::addable()
+    pub selected_module: usize,
+    // rotation of the module being built, relative to the root
+    pub build_rotation: f32,
+    // set by the build palette while a module is dragged out of it, and when
+    // it is let go
+    pub dragging_module: bool,
+    pub dropped_module: bool,
+    // clicks on the ui should not build anything in the world behind it
+    pub pointer_over_ui: bool,
+    pub flight_assist: bool,
+    // toggled with tab
+    pub show_scoreboard: bool,
+    // recent bullet hits and how long ago they happened
+    hits: Vec<(Hit, f32)>,
+    // the last build the server refused and how long ago
+    build_error: Option<(BuildRejection, f32)>,
 }
 
 const STARS_VERT: &str = include_str!("./shaders/stars.vert");
 const STARS_FRAG: &str = include_str!("./shaders/stars.frag");
 
 impl ClientState {
-    pub fn new(my_id: u64) -> ClientState {
+    pub fn new(my_id: u64, config: GameConfig) -> ClientState {
         let stars_material = macroquad::material::load_material(
             STARS_VERT,
             STARS_FRAG,
@@ -50,17 +70,27 @@ impl ClientState {
 
         ClientState {
             my_id,
-            stars: Self::init_stars(),
+            stars: Self::init_stars(config.world_size),
+            config,
             stars_material,
             is_building: false,
+            selected_module: 0,
+            build_rotation: 0.,
+            dragging_module: false,
+            dropped_module: false,
+            pointer_over_ui: false,
+            flight_assist: false,
+            show_scoreboard: false,
+            hits: vec![],
+            build_error: None,
         }
     }
 
-    fn init_stars() -> Vec<Star> {
+    fn init_stars(world_size: f32) -> Vec<Star> {
         let mut stars = vec![];
         for _ in 0..constants::NUM_STARS {
-            let x = gen_range(-constants::WORLD_SIZE as i32, 2 * constants::WORLD_SIZE as i32);
-            let y = gen_range(-constants::WORLD_SIZE, 2. * constants::WORLD_SIZE);
+            let x = gen_range(-world_size as i32, 2 * world_size as i32);
+            let y = gen_range(-world_size, 2. * world_size);
             stars.push(Star {
                 x: x as f32,
                 y: y as f32,
@@ -70,57 +100,127 @@ impl ClientState {
         stars
     }
 
+    pub fn add_hits(&mut self, hits: &[Hit]) {
+        self.hits.extend(hits.iter().map(|hit| (hit.clone(), 0.)));
+    }
+
+    pub fn selected_spec(&self) -> ComponentSpecialization {
+        ComponentSpecialization::addable()[self.selected_module].clone()
+    }
+
+    pub fn select_module(&mut self, index: usize) {
+        if index < ComponentSpecialization::addable().len() {
+            self.selected_module = index;
+            self.is_building = true;
+        }
+    }
+
     pub fn update(
         &mut self,
-        _delta_time: f32,
+        delta_time: f32,
         game_state: &mut GameState,
         my_id: u64,
         client_messages: &mut Vec<ClientMessage>,
     ) {
+        for (_, age) in &mut self.hits {
+            *age += delta_time;
+        }
+        self.hits
+            .retain(|(_, age)| *age < constants::HIT_EFFECT_DURATION);
+        if let Some((_, age)) = &mut self.build_error {
+            *age += delta_time;
+            if *age > constants::BUILD_ERROR_DURATION {
+                self.build_error = None;
+            }
+        }
+
+        if is_key_pressed(KeyCode::Tab) {
+            self.show_scoreboard = !self.show_scoreboard;
+        }
+
         let player = self.my_player(my_id, game_state);
         if let Some(p) = player {
             if is_key_pressed(KeyCode::B) {
                 self.is_building = !self.is_building;
             }
+            if is_key_pressed(KeyCode::F) {
+                self.flight_assist = !self.flight_assist;
+            }
+            if is_key_pressed(KeyCode::T) && game_state.mode.kind.has_teams() {
+                if let Some(team) = p.team {
+                    client_messages.push(ClientMessage::SwitchTeam {
+                        team: (team + 1) % self.config.teams,
+                    });
+                }
+            }
+
+            let hotkeys = [
+                KeyCode::Key1,
+                KeyCode::Key2,
+                KeyCode::Key3,
+                KeyCode::Key4,
+                KeyCode::Key5,
+                KeyCode::Key6,
+                KeyCode::Key7,
+                KeyCode::Key8,
+                KeyCode::Key9,
+            ];
+            for (index, key) in hotkeys.iter().enumerate() {
+                if is_key_pressed(*key) {
+                    self.select_module(index);
+                }
+            }
+
+            if self.is_building || self.dragging_module {
+                let scroll = if self.pointer_over_ui {
+                    0.
+                } else {
+                    mouse_wheel().1
+                };
+                if is_key_pressed(KeyCode::Q) || scroll > 0. {
+                    self.build_rotation -= constants::MODULE_ROTATION_STEP;
+                }
+                if is_key_pressed(KeyCode::E) || scroll < 0. {
+                    self.build_rotation += constants::MODULE_ROTATION_STEP;
+                }
+                self.build_rotation =
+                    math::snap_angle(self.build_rotation, constants::MODULE_ROTATION_STEP);
+            }
 
             let mouse_world_pos = Self::mouse_world_pos(p);
-            if self.is_building
+            let clicked = self.is_building && is_mouse_button_pressed(MouseButton::Left);
+            let dropped = std::mem::take(&mut self.dropped_module);
+            if (clicked || dropped)
+                && !self.pointer_over_ui
                 && self.is_valid_component_pos(my_id, game_state, mouse_world_pos)
-                && is_mouse_button_pressed(MouseButton::Left)
             {
-                println!("Building component");
-
                 client_messages.push(ClientMessage::AddComponent {
                     world_pos: mouse_world_pos,
-                    specialization: ComponentSpecialization::addable()
-                        .choose()
-                        .unwrap()
-                        .clone(),
+                    specialization: self.selected_spec(),
+                    rotation: self.build_rotation,
                 });
             }
         }
     }
 
+    /// Runs the same checks as the server so that the ghost shows whether
+    /// the module can be built
     pub fn is_valid_component_pos(
         &self,
         my_id: u64,
         game_state: &GameState,
-        math::Vec2 { x, y }: math::Vec2,
+        pos: math::Vec2,
     ) -> bool {
-        if let Some(p) = self.my_player(my_id, game_state) {
-            let in_range = p
-                .components
-                .iter()
-                .any(|c| (c.pos - math::vec2(x, y)).norm().abs() < constants::MODULE_RADIUS * 4.);
-            let overlap = p
-                .components
-                .iter()
-                .any(|c| (c.pos - math::vec2(x, y)).norm().abs() < constants::MODULE_RADIUS * 2.);
-
-            in_range && !overlap
-        } else {
-            false
-        }
+        self.my_player(my_id, game_state)
+            .map(|p| {
+                p.check_build(&self.selected_spec(), pos, &self.config)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn build_rejected(&mut self, reason: BuildRejection) {
+        self.build_error = Some((reason, 0.));
     }
 
     pub fn draw(&mut self, my_id: u64, game_state: &GameState, assets: &Assets) -> Result<()> {
@@ -129,21 +229,7 @@ impl ClientState {
         let player = self.my_player(my_id, game_state);
         if let Some(p) = player {
             if p.requesting_death {
-                clear_background(RED);
-                draw_text(
-                    "u ded, spes no inifity",
-                    screen_width() as f32 / 2.0,
-                    screen_height() as f32 / 2.0,
-                    100.,
-                    BLACK,
-                );
-                draw_text(
-                    "pres D for again",
-                    screen_width() as f32 / 2.0,
-                    screen_height() as f32 / 2.0 + 100.,
-                    70.,
-                    BLACK,
-                );
+                Self::draw_death_screen(p, game_state);
             } else {
                 // TODO: no whoami
                 if false { //whoami::hostname() == "ares" || whoami::hostname() == "spirit"
@@ -160,7 +246,7 @@ impl ClientState {
                     screen_height() as f32 / 2.0 - self_pos.y,
                 );
 
-                Self::draw_bounds(self_pos.x, self_pos.y);
+                Self::draw_bounds(self.config.world_size, self_pos.x, self_pos.y);
 
                 for asteroid in &game_state.asteroids {
                     let (x, y) = (
@@ -172,43 +258,92 @@ impl ClientState {
                         x,
                         y,
                         asteroid.angle,
-                        Vec2::new(ASTEROID_SIZE, ASTEROID_SIZE),
+                        Vec2::new(asteroid.size, asteroid.size),
+                    );
+                }
+
+                for debris in &game_state.debris {
+                    let component = &debris.component;
+                    rendering::draw_texture_centered_size(
+                        assets.module_icon(&component.spec),
+                        center.x + component.pos.x,
+                        center.y + component.pos.y,
+                        component.angle,
+                        Vec2 { x: 64., y: 64. },
                     );
                 }
 
+                for player in &game_state.players {
+                    for (index, handle) in &player.mining_beams {
+                        let laser = player.components.get(*index);
+                        let asteroid = game_state.asteroids.iter().find(|a| a.handle == *handle);
+                        if let (Some(laser), Some(asteroid)) = (laser, asteroid) {
+                            draw_line(
+                                center.x + laser.pos.x,
+                                center.y + laser.pos.y,
+                                center.x + asteroid.x,
+                                center.y + asteroid.y,
+                                3.,
+                                Color::new(1., 0.6, 0.2, 0.8),
+                            );
+                        }
+                    }
+                }
+
                 for player in &game_state.players {
                     Self::draw_shield(player, self_pos.x, self_pos.y);
 
+                    if player.spawn_protection > 0. {
+                        let pos = center + Vec2::new(player.position().x, player.position().y);
+                        let radius = player
+                            .components
+                            .iter()
+                            .map(|c| (c.pos - player.position()).norm())
+                            .fold(0., f32::max)
+                            + constants::MODULE_RADIUS * 2.;
+                        draw_circle_lines(pos.x, pos.y, radius, 3., Color::new(1., 1., 1., 0.5));
+                    }
+
                     for component in &player.components {
                         let (x, y) = (center.x + component.pos.x, center.y + component.pos.y);
 
                         use ComponentSpecialization as CS;
                         let spec = &component.spec;
+                        let tint = rendering::team_color(player.team);
 
                         let bg_sprite = match spec {
-                            CS::Cannon { .. } => Some(assets.node_bg),
+                            CS::Cannon { .. } | CS::Battery | CS::MiningLaser => {
+                                Some(assets.node_bg)
+                            }
                             CS::Reactionwheel { .. } => Some(assets.reaction_wheel_bot),
                             _ => None,
                         };
 
                         if let Some(s) = bg_sprite {
-                            rendering::draw_texture_centered_size(
+                            rendering::draw_texture_centered_size_color(
                                 s,
                                 x,
                                 y,
                                 component.angle,
                                 Vec2 { x: 64., y: 64. },
+                                tint,
                             );
                         }
 
                         if let CS::Thrusters = spec {
-                            let angle = if player.input_y < -0.5 {
+                            let throttle = player
+                                .thruster_throttles
+                                .iter()
+                                .find(|(handle, _)| *handle == component.physics_handle)
+                                .map(|(_, throttle)| *throttle)
+                                .unwrap_or(0.);
+                            let angle = if throttle < 0. {
                                 0.
                             } else {
                                 std::f32::consts::PI
                             };
 
-                            if player.input_y.abs() > 0.5 {
+                            if throttle.abs() > 0.1 {
                                 rendering::draw_texture_centered_size(
                                     assets.thrust_flame,
                                     x,
@@ -223,21 +358,28 @@ impl ClientState {
                             CS::Root
                             | CS::Shield
                             | CS::Cannon { aim: false, .. }
-                            | CS::Thrusters => {
+                            | CS::Thrusters
+                            | CS::Generator
+                            | CS::Battery
+                            | CS::MiningLaser => {
                                 let fg_sprite = match spec {
                                     CS::Root => assets.root_node,
                                     CS::Shield => assets.shield,
                                     CS::Thrusters => assets.thrusters,
                                     CS::Cannon { .. } => assets.cannon,
+                                    CS::Generator => assets.generator,
+                                    CS::Battery => assets.battery,
+                                    CS::MiningLaser => assets.mining_laser,
                                     _ => unreachable!(),
                                 };
 
-                                rendering::draw_texture_centered_size(
+                                rendering::draw_texture_centered_size_color(
                                     fg_sprite,
                                     x,
                                     y,
                                     component.angle,
                                     Vec2 { x: 64., y: 64. },
+                                    tint,
                                 );
                             }
                             CS::Reactionwheel { angle } => {
@@ -277,6 +419,18 @@ impl ClientState {
                             draw_circle_lines(x, y, 32., 1., RED);
                         }
                     }
+
+                    if player.id != my_id && !player.requesting_death {
+                        let pos = center + Vec2::new(player.position().x, player.position().y);
+                        let size = measure_text(&player.name, None, 20, 1.);
+                        draw_text(
+                            &player.name,
+                            pos.x - size.width / 2.,
+                            pos.y - 80.,
+                            20.,
+                            rendering::team_color(player.team),
+                        );
+                    }
                 }
                 for bullet in &game_state.bullets {
                     rendering::draw_texture_centered(
@@ -286,23 +440,246 @@ impl ClientState {
                         bullet.angle,
                     );
                 }
-                if self.is_building {
+                self.draw_hits(center);
+                if self.is_building || self.dragging_module {
                     let (x, y) = mouse_position();
 
-                    if self.is_valid_component_pos(my_id, game_state, Self::mouse_world_pos(p)) {
-                        draw_circle_lines(x, y, constants::MODULE_RADIUS, 1., BLUE);
-                        draw_circle_lines(x, y, constants::MODULE_RADIUS * 2., 1., PURPLE)
+                    // Ghost of the module that would be built
+                    rendering::draw_texture_centered_size_color(
+                        assets.module_icon(&self.selected_spec()),
+                        x,
+                        y,
+                        p.angle() + self.build_rotation,
+                        Vec2 { x: 64., y: 64. },
+                        Color::new(1., 1., 1., 0.5),
+                    );
+
+                    let valid =
+                        self.is_valid_component_pos(my_id, game_state, Self::mouse_world_pos(p));
+                    let (color, outer) = if valid {
+                        (BLUE, PURPLE)
                     } else {
-                        draw_circle_lines(x, y, constants::MODULE_RADIUS, 1., ORANGE);
-                        draw_circle_lines(x, y, constants::MODULE_RADIUS * 2., 1., ORANGE)
-                    }
+                        (ORANGE, ORANGE)
+                    };
+                    draw_circle_lines(x, y, constants::MODULE_RADIUS, 1., color);
+                    draw_circle_lines(x, y, constants::MODULE_RADIUS * 2., 1., outer);
+
+                    // Which way the module will face
+                    let facing = math::Vec2::from_direction(
+                        p.angle() + self.build_rotation - PI / 2.,
+                        constants::MODULE_RADIUS,
+                    );
+                    draw_line(x, y, x + facing.x, y + facing.y, 2., color);
+                }
+
+                if let Some((reason, _)) = &self.build_error {
+                    let text = reason.to_string();
+                    let size = measure_text(&text, None, 30, 1.);
+                    draw_text(
+                        &text,
+                        (screen_width() - size.width) / 2.,
+                        screen_height() / 4.,
+                        30.,
+                        ORANGE,
+                    );
                 }
             }
         }
 
+        self.draw_mode(my_id, game_state);
+        Self::draw_killfeed(my_id, game_state);
+
         Ok(())
     }
 
+    fn draw_death_screen(player: &Player, game_state: &GameState) {
+        clear_background(Color::new(0.3, 0., 0., 1.));
+
+        let killer = player
+            .killer()
+            .and_then(|id| game_state.get_player_by_id(id))
+            .map(|killer| killer.name.clone());
+        let cause = match (player.death_cause, killer) {
+            (Some(KillCause::Bullet), Some(killer)) => format!("Shot down by {}", killer),
+            (Some(KillCause::Bullet), None) => "Shot down".to_string(),
+            (Some(KillCause::Asteroid), Some(killer)) => {
+                format!("Crashed into an asteroid fleeing {}", killer)
+            }
+            (Some(KillCause::Asteroid), None) => "Crashed into an asteroid".to_string(),
+            (Some(KillCause::OutOfBounds), Some(killer)) => {
+                format!("Driven out of bounds by {}", killer)
+            }
+            (_, _) => "Lost in space".to_string(),
+        };
+        let respawn = if !game_state.mode.allows_respawn() {
+            "Waiting for the next round".to_string()
+        } else if player.respawn_timer > 0. {
+            format!("Respawn in {:.1}s", player.respawn_timer)
+        } else {
+            "Press D to respawn".to_string()
+        };
+
+        // Players who joined during an elimination round have not died yet
+        let (title, cause) = if player.has_ship() {
+            ("You died", cause)
+        } else {
+            ("Round in progress", "You join when it is over".to_string())
+        };
+        let lines = [
+            (title, 100, WHITE),
+            (&cause[..], 50, WHITE),
+            (&respawn[..], 40, GRAY),
+        ];
+        let mut y = screen_height() / 2. - 100.;
+        for (text, size, color) in lines {
+            let dimensions = measure_text(text, None, size, 1.);
+            draw_text(
+                text,
+                (screen_width() - dimensions.width) / 2.,
+                y,
+                size as f32,
+                color,
+            );
+            y += dimensions.height + 40.;
+        }
+    }
+
+    /// The mode, the phase of the round and how long it has left
+    fn draw_mode(&self, my_id: u64, game_state: &GameState) {
+        let mode = &game_state.mode;
+        let name = |id: u64| {
+            game_state
+                .get_player_by_id(id)
+                .map(|p| p.name.clone())
+                .unwrap_or_else(|| "someone".to_string())
+        };
+        let time = if mode.time_left.is_finite() {
+            let seconds = mode.time_left.max(0.).ceil() as u32;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        } else {
+            "".to_string()
+        };
+
+        let status = match mode.phase {
+            Phase::Warmup if game_state.players.len() < self.config.min_players => format!(
+                "Waiting for players {}/{}",
+                game_state.players.len(),
+                self.config.min_players
+            ),
+            Phase::Warmup => format!("Round starts in {}", time),
+            Phase::Active if mode.kind.has_teams() => {
+                let scores = (0..self.config.teams)
+                    .map(|team| format!("{} {}", GameMode::team_name(team), mode.team_score(team)))
+                    .collect::<Vec<_>>();
+                format!(
+                    "{} / {}  {}",
+                    scores.join(" - "),
+                    self.config.score_limit,
+                    time
+                )
+            }
+            Phase::Active if mode.kind == GameModeKind::Elimination => {
+                let alive = game_state
+                    .players
+                    .iter()
+                    .filter(|p| !p.requesting_death)
+                    .count();
+                format!("{} ships left  {}", alive, time)
+            }
+            Phase::Active => {
+                let kills = self
+                    .my_player(my_id, game_state)
+                    .map(|p| p.stats.kills)
+                    .unwrap_or(0);
+                format!("{}/{} kills  {}", kills, self.config.score_limit, time)
+            }
+            Phase::RoundOver { winner } => match winner {
+                Winner::Player(id) => format!("{} wins the round", name(id)),
+                Winner::Team(team) => format!("{} team wins the round", GameMode::team_name(team)),
+                Winner::Draw => "The round is a draw".to_string(),
+            },
+        };
+
+        let lines = [(mode.kind.name().to_string(), 20), (status, 30)];
+        let mut y = 30.;
+        for (text, size) in lines {
+            let dimensions = measure_text(&text, None, size, 1.);
+            draw_text(
+                &text,
+                (screen_width() - dimensions.width) / 2.,
+                y,
+                size as f32,
+                WHITE,
+            );
+            y += dimensions.height + 10.;
+        }
+    }
+
+    fn draw_killfeed(my_id: u64, game_state: &GameState) {
+        let name = |id: u64| {
+            game_state
+                .get_player_by_id(id)
+                .map(|p| p.name.clone())
+                .unwrap_or_else(|| "someone".to_string())
+        };
+
+        for (i, message) in game_state.killfeed.get_messages().iter().enumerate() {
+            let kill = &message.kill;
+            let victim = name(kill.victim);
+            let text = match (kill.cause, kill.killer.map(name)) {
+                (KillCause::Bullet, Some(killer)) => format!("{} shot {}", killer, victim),
+                (KillCause::Bullet, None) => format!("{} was shot", victim),
+                (KillCause::Asteroid, Some(killer)) => {
+                    format!("{} hit an asteroid fleeing {}", victim, killer)
+                }
+                (KillCause::Asteroid, None) => format!("{} hit an asteroid", victim),
+                (KillCause::OutOfBounds, Some(killer)) => {
+                    format!("{} drove {} out of bounds", killer, victim)
+                }
+                (KillCause::OutOfBounds, None) => format!("{} flew out of bounds", victim),
+            };
+
+            let involves_me = kill.victim == my_id || kill.killer == Some(my_id);
+            let color = if involves_me { YELLOW } else { WHITE };
+            // Fade out during the last quarter of the time on screen
+            let alpha = ((1. - message.age(game_state.server_time)) * 4.).min(1.);
+
+            let size = measure_text(&text, None, 24, 1.);
+            draw_text(
+                &text,
+                screen_width() - size.width - 20.,
+                40. + i as f32 * 30.,
+                24.,
+                Color { a: alpha, ..color },
+            );
+        }
+    }
+
+    fn draw_hits(&self, center: Vec2) {
+        for (hit, age) in &self.hits {
+            let progress = age / constants::HIT_EFFECT_DURATION;
+            let (color, size) = match hit.target {
+                HitTarget::Component {
+                    destroyed: true, ..
+                } => (RED, 64.),
+                HitTarget::Component { .. } => (ORANGE, 24.),
+                HitTarget::Shield { .. } => (YELLOW, 16.),
+                HitTarget::Asteroid { destroyed: true } => (GRAY, 96.),
+                HitTarget::Asteroid { .. } => (GRAY, 16.),
+            };
+
+            draw_circle(
+                center.x + hit.pos.x,
+                center.y + hit.pos.y,
+                size * (0.5 + progress),
+                Color {
+                    a: 1. - progress,
+                    ..color
+                },
+            );
+        }
+    }
+
     fn draw_shield(player: &Player, self_x: f32, self_y: f32) {
         let pos = player.position();
 
@@ -312,7 +689,7 @@ impl ClientState {
                 screen_height() / 2. - self_y + v.y,
             );
 
-            let alpha = if player.shielding { 1. } else { 0.1 };
+            let alpha = if player.shield.active { 1. } else { 0.1 };
             let color = Color {
                 r: 1.,
                 g: 1.,
@@ -335,21 +712,15 @@ impl ClientState {
         game_state.players.iter().find(|p| p.id == my_id)
     }
 
-    fn draw_bounds(player_x: f32, player_y: f32) {
+    fn draw_bounds(world_size: f32, player_x: f32, player_y: f32) {
         let sx = screen_width() / 2.;
         let sy = screen_height() / 2.;
 
         let lines = vec![
-            ((0., 0.), (0., constants::WORLD_SIZE)),
-            (
-                (0., constants::WORLD_SIZE),
-                (constants::WORLD_SIZE, constants::WORLD_SIZE),
-            ),
-            (
-                (constants::WORLD_SIZE, constants::WORLD_SIZE),
-                (constants::WORLD_SIZE, 0.),
-            ),
-            ((constants::WORLD_SIZE, 0.), (0., 0.)),
+            ((0., 0.), (0., world_size)),
+            ((0., world_size), (world_size, world_size)),
+            ((world_size, world_size), (world_size, 0.)),
+            ((world_size, 0.), (0., 0.)),
         ];
 
         for ((x1, y1), (x2, y2)) in lines {
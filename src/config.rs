@@ -0,0 +1,133 @@
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use libplen::config::GameConfig;
+use libplen::constants;
+use libplen::messages::{DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_QUEUED_BYTES};
+use serde_derive::Deserialize;
+
+const USAGE: &str = "Usage: server [options]
+
+Options:
+    --config <file>             TOML file to read the settings from
+    --bind <address>            Address to listen on
+    --port <port>               Port to listen on, for both tcp and udp
+    --tick-rate <ticks>         Server updates per second
+    --world-size <size>         Width and height of the world
+    --asteroids <count>         Asteroids in the world at startup
+    --asteroid-interval <secs>  Seconds between new asteroids
+    --max-asteroids <count>     Most asteroids there can be at once
+    --max-players <count>       Players that can be in the game at once
+    --connection-timeout <secs> Seconds of silence before a client is dropped
+    --max-queued-bytes <bytes>  Unsent data a tcp client may have before it
+                                is disconnected
+    --max-frame-size <bytes>    Largest message a client may send
+
+Flags override the values in the config file";
+
+/// Everything the server can be configured with. The game part is sent to
+/// clients when they connect
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    // seconds without messages before a client is dropped
+    pub connection_timeout: f32,
+    pub max_queued_bytes: usize,
+    pub max_frame_size: usize,
+    pub game: GameConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "0.0.0.0".into(),
+            port: 4444,
+            connection_timeout: constants::CONNECTION_TIMEOUT,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            game: GameConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<ServerConfig> {
+        let args = args.collect::<Vec<_>>();
+
+        // The file is read first so that the flags can override it
+        let config_file = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .map(|i| args.get(i + 1).context("--config needs a value"))
+            .transpose()?;
+        let mut config = match config_file {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--config" => {
+                    args.next();
+                }
+                "--bind" => config.bind = value(flag, args.next())?,
+                "--port" => config.port = value(flag, args.next())?,
+                "--tick-rate" => config.game.tick_rate = value(flag, args.next())?,
+                "--world-size" => config.game.world_size = value(flag, args.next())?,
+                "--asteroids" => config.game.initial_asteroids = value(flag, args.next())?,
+                "--asteroid-interval" => {
+                    config.game.asteroid_spawn_interval = value(flag, args.next())?
+                }
+                "--max-asteroids" => config.game.max_asteroids = value(flag, args.next())?,
+                "--max-players" => config.game.max_players = value(flag, args.next())?,
+                "--connection-timeout" => config.connection_timeout = value(flag, args.next())?,
+                "--max-queued-bytes" => config.max_queued_bytes = value(flag, args.next())?,
+                "--max-frame-size" => config.max_frame_size = value(flag, args.next())?,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => bail!("Unknown option {}\n\n{}", flag, USAGE),
+            }
+        }
+
+        if config.game.tick_rate == 0 {
+            bail!("The tick rate has to be at least 1");
+        }
+        if config.game.world_size <= 0. {
+            bail!("The world size has to be positive");
+        }
+        if config.connection_timeout <= 0. {
+            bail!("The connection timeout has to be positive");
+        }
+        if !config.game.score.is_finite() {
+            bail!("The score weights have to be finite");
+        }
+        if config.game.mode.has_teams() && config.game.teams < 2 {
+            bail!("{} needs at least 2 teams", config.game.mode.name());
+        }
+
+        Ok(config)
+    }
+
+    fn load(path: &str) -> Result<ServerConfig> {
+        let text = fs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+        toml::from_str(&text).with_context(|| format!("Could not parse {}", path))
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+}
+
+fn value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T> {
+    let value = value.with_context(|| format!("{} needs a value", flag))?;
+    match value.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => bail!("Invalid value {} for {}", value, flag),
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use libplen::constants;
+use libplen::gamestate::GameState;
+use libplen::math::{angle_diff, Vec2};
+use libplen::player::Player;
+
+// Render time is snapped instead of nudged if it is further off than this
+const MAX_CLOCK_DRIFT: f64 = 0.25;
+// Fraction of the clock error corrected every frame
+const CLOCK_CORRECTION: f64 = 0.05;
+
+/// Buffers snapshots from the server and renders the world slightly in the
+/// past so that there are two snapshots to interpolate between. If the
+/// snapshots run out, entities keep moving with their last known velocity
+/// for a short while
+pub struct Interpolator {
+    snapshots: VecDeque<(f64, GameState)>,
+    pub delay: f32,
+    render_time: Option<f64>,
+}
+
+impl Interpolator {
+    pub fn new(delay: f32) -> Interpolator {
+        Interpolator {
+            snapshots: VecDeque::new(),
+            delay,
+            render_time: None,
+        }
+    }
+
+    pub fn push(&mut self, state: GameState) {
+        let time = state.server_time;
+        if let Some((latest, _)) = self.snapshots.back() {
+            if time <= *latest {
+                return;
+            }
+        }
+
+        self.snapshots.push_back((time, state));
+        if self.snapshots.len() > constants::SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Advances the render clock by delta and returns the state to draw
+    pub fn advance(&mut self, delta: f32) -> Option<GameState> {
+        let latest = self.snapshots.back()?.0;
+        let target = latest - self.delay as f64;
+
+        let time = match self.render_time {
+            Some(time) if (time + delta as f64 - target).abs() < MAX_CLOCK_DRIFT => {
+                let time = time + delta as f64;
+                time + (target - time) * CLOCK_CORRECTION
+            }
+            _ => target,
+        };
+        let time = time.min(latest + constants::MAX_EXTRAPOLATION as f64);
+        self.render_time = Some(time);
+
+        // Keep the last snapshot before the render time around
+        while self.snapshots.len() > 1 && self.snapshots[1].0 <= time {
+            self.snapshots.pop_front();
+        }
+
+        let (from_time, from) = self.snapshots.front()?;
+        if time < *from_time {
+            return Some(from.clone());
+        }
+
+        Some(match self.snapshots.get(1) {
+            Some((to_time, to)) => {
+                let t = ((time - from_time) / (to_time - from_time)) as f32;
+                interpolate(from, to, t)
+            }
+            None => extrapolate(from, (time - from_time) as f32),
+        })
+    }
+}
+
+fn lerp(from: Vec2, to: Vec2, t: f32) -> Vec2 {
+    from + (to - from) * t
+}
+
+fn lerp_angle(from: f32, to: f32, t: f32) -> f32 {
+    from + angle_diff(from, to) * t
+}
+
+// The shield points are in world coordinates, so they have to follow the
+// core when it is moved
+fn move_shield(player: &mut Player, offset: Vec2) {
+    for point in &mut player.shield.points {
+        point.x += offset.x;
+        point.y += offset.y;
+    }
+}
+
+fn interpolate(from: &GameState, to: &GameState, t: f32) -> GameState {
+    let mut state = to.clone();
+
+    for player in &mut state.players {
+        let Some(old) = from.players.iter().find(|p| p.id == player.id) else {
+            continue;
+        };
+
+        let original = player.position();
+        for component in &mut player.components {
+            let old = old
+                .components
+                .iter()
+                .find(|c| c.physics_handle == component.physics_handle);
+            if let Some(old) = old {
+                component.pos = lerp(old.pos, component.pos, t);
+                component.angle = lerp_angle(old.angle, component.angle, t);
+            }
+        }
+        let offset = player.position() - original;
+        move_shield(player, offset);
+    }
+
+    for bullet in &mut state.bullets {
+        if let Some(old) = from.bullets.iter().find(|b| b.handle == bullet.handle) {
+            bullet.pos = lerp(old.pos, bullet.pos, t);
+            bullet.angle = lerp_angle(old.angle, bullet.angle, t);
+        }
+    }
+
+    for asteroid in &mut state.asteroids {
+        if let Some(old) = from.asteroids.iter().find(|a| a.handle == asteroid.handle) {
+            asteroid.x = old.x + (asteroid.x - old.x) * t;
+            asteroid.y = old.y + (asteroid.y - old.y) * t;
+            asteroid.angle = lerp_angle(old.angle, asteroid.angle, t);
+        }
+    }
+
+    for debris in &mut state.debris {
+        if let Some(old) = from
+            .debris
+            .iter()
+            .find(|d| d.component.physics_handle == debris.component.physics_handle)
+        {
+            debris.component.pos = lerp(old.component.pos, debris.component.pos, t);
+            debris.component.angle = lerp_angle(old.component.angle, debris.component.angle, t);
+        }
+    }
+
+    state
+}
+
+fn extrapolate(from: &GameState, dt: f32) -> GameState {
+    let mut state = from.clone();
+
+    for player in &mut state.players {
+        let original = player.position();
+        for component in &mut player.components {
+            component.pos += component.linvel * dt;
+            component.angle += component.angvel * dt;
+        }
+        let offset = player.position() - original;
+        move_shield(player, offset);
+    }
+
+    for bullet in &mut state.bullets {
+        bullet.pos += bullet.linvel * dt;
+    }
+
+    for asteroid in &mut state.asteroids {
+        asteroid.x += asteroid.linvel.x * dt;
+        asteroid.y += asteroid.linvel.y * dt;
+        asteroid.angle += asteroid.angvel * dt;
+    }
+
+    for debris in &mut state.debris {
+        debris.component.pos += debris.component.linvel * dt;
+        debris.component.angle += debris.component.angvel * dt;
+    }
+
+    state
+}
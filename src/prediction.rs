@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use libplen::config::GameConfig;
+use libplen::constants;
+use libplen::math;
+use libplen::messages::ClientInput;
+use libplen::physics::PhysicsState;
+use libplen::player::{Player, Shield};
+use rapier2d::prelude::*;
+
+// Don't try to catch up on more than this after a long frame
+const MAX_TICKS_PER_FRAME: u32 = 10;
+// Inputs are dropped if the server stops acknowledging them
+const MAX_PENDING_INPUTS: usize = 256;
+
+/// Simulates our own ship locally so that it reacts to input without waiting
+/// for the server. When an authoritative state arrives, the ship is reset to
+/// it and the inputs the server has not seen yet are replayed on top
+pub struct Predictor {
+    config: GameConfig,
+    physics: PhysicsState,
+    player: Option<Player>,
+    // Inputs the server has not finished applying yet
+    pending: VecDeque<ClientInput>,
+    next_sequence: u32,
+    accumulator: f32,
+}
+
+impl Predictor {
+    pub fn new(config: GameConfig) -> Predictor {
+        Predictor {
+            config,
+            physics: PhysicsState::new(),
+            player: None,
+            pending: VecDeque::new(),
+            // 0 means that the server has not seen any input
+            next_sequence: 1,
+            accumulator: 0.,
+        }
+    }
+
+    pub fn next_sequence(&mut self) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        sequence
+    }
+
+    pub fn predicted(&self) -> Option<&Player> {
+        self.player.as_ref()
+    }
+
+    /// Rewinds to the server's version of our ship and replays the input
+    /// ticks it has not simulated yet. The server was input_ticks into the
+    /// input numbered last_input when it took the state
+    pub fn reconcile(&mut self, server_player: Option<&Player>, last_input: u32, input_ticks: u32) {
+        while let Some(input) = self.pending.front() {
+            let applied = input.sequence < last_input
+                || input.sequence == last_input && input.ticks <= input_ticks;
+            if !applied {
+                break;
+            }
+            self.pending.pop_front();
+        }
+
+        let Some(server_player) = server_player.filter(|player| player.has_ship()) else {
+            self.player = None;
+            return;
+        };
+
+        self.rebuild(server_player);
+        let pending = self.pending.clone();
+        for input in &pending {
+            let done = if input.sequence == last_input {
+                input_ticks
+            } else {
+                0
+            };
+            for _ in done..input.ticks {
+                self.tick(input);
+            }
+        }
+    }
+
+    /// Advances the local ship by the ticks that fit in delta and records
+    /// their number in the input, for the server to apply it as long
+    pub fn predict(&mut self, input: &mut ClientInput, delta: f32) {
+        let tick_time = self.config.delta_time();
+        self.accumulator += delta;
+        let ticks = ((self.accumulator / tick_time) as u32).min(MAX_TICKS_PER_FRAME);
+        self.accumulator = (self.accumulator - ticks as f32 * tick_time).min(tick_time);
+
+        input.ticks = ticks;
+        for _ in 0..ticks {
+            self.tick(input);
+        }
+
+        self.pending.push_back(input.clone());
+        if self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+    }
+
+    fn tick(&mut self, input: &ClientInput) {
+        let Some(player) = &mut self.player else {
+            return;
+        };
+
+        // Our shots show up when the server confirms them
+        let mut bullets = vec![];
+        player.set_input(input);
+        player.update(
+            self.config.delta_time(),
+            &mut bullets,
+            &mut self.physics,
+            &self.config,
+        );
+        self.physics.step(self.config.delta_time());
+        player.sync_physics(&self.physics.rigid_body_set);
+    }
+
+    fn rebuild(&mut self, server_player: &Player) {
+        self.physics = PhysicsState::new();
+        let p = &mut self.physics;
+
+        let mut player = Player {
+            components: vec![],
+            shield: Shield::new(),
+            ..server_player.clone()
+        };
+
+        // The root has to be in place before the rest is attached to it
+        let root_angle = server_player.angle();
+        for component in &server_player.components {
+            let rotation = math::snap_angle(
+                component.angle - root_angle,
+                constants::MODULE_ROTATION_STEP,
+            );
+            player.add_component(
+                component.spec.clone(),
+                p,
+                &self.config,
+                (component.pos.x, component.pos.y),
+                rotation,
+            );
+
+            let local = player.components.last_mut().unwrap();
+            let rb = p.rigid_body_set.get_mut(local.physics_handle).unwrap();
+            rb.set_position(
+                Isometry::new(vector![component.pos.x, component.pos.y], component.angle),
+                true,
+            );
+            rb.set_linvel(vector![component.linvel.x, component.linvel.y], true);
+            rb.set_angvel(component.angvel, true);
+
+            local.angle = component.angle;
+            local.linvel = component.linvel;
+            local.angvel = component.angvel;
+            local.hp = component.hp;
+        }
+
+        player.shield.radius = server_player.shield.radius;
+        player.shield.active = server_player.shield.active;
+        player.set_num_shield_points(server_player.shield.num_points, p);
+        self.player = Some(player);
+    }
+}
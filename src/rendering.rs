@@ -28,6 +28,17 @@ pub fn draw_texture_centered_size(
     y: f32,
     angle: f32,
     size: Vec2,
+) {
+    draw_texture_centered_size_color(texture, x, y, angle, size, WHITE)
+}
+
+pub fn draw_texture_centered_size_color(
+    texture: texture::Texture2D,
+    x: f32,
+    y: f32,
+    angle: f32,
+    size: Vec2,
+    color: Color,
 ) {
     let params = texture::DrawTextureParams {
         dest_size: Some(size),
@@ -38,7 +49,19 @@ pub fn draw_texture_centered_size(
         pivot: None,
     };
 
-    texture::draw_texture_ex(texture, x - size.x / 2., y - size.y / 2., WHITE, params);
+    texture::draw_texture_ex(texture, x - size.x / 2., y - size.y / 2., color, params);
+}
+
+/// What a ship's modules are tinted with. Ships without a team keep their
+/// own colors
+pub fn team_color(team: Option<u8>) -> Color {
+    match team {
+        None => WHITE,
+        Some(0) => Color::new(1., 0.5, 0.5, 1.),
+        Some(1) => Color::new(0.5, 0.7, 1., 1.),
+        Some(2) => Color::new(0.5, 1., 0.5, 1.),
+        Some(_) => Color::new(1., 1., 0.5, 1.),
+    }
 }
 
 /*
@@ -1,101 +1,113 @@
+mod config;
+
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
-use std::io::prelude::*;
 use std::net::TcpListener;
-use std::net::TcpStream;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::vec;
 
-use libplen::math::vec2;
+use config::ServerConfig;
+
+use libplen::math::{self, vec2};
 use libplen::player::Component;
 use libplen::player::ComponentSpecialization;
 use rapier2d::prelude::*;
 use unicode_truncate::UnicodeTruncateStr;
 
+use libplen::config::GameConfig;
 use libplen::constants;
 use libplen::gamestate;
-use libplen::messages::{ClientInput, ClientMessage, MessageReader, ServerMessage};
+use libplen::messages::{
+    ClientInput, ClientMessage, GameStateDelta, QueueFull, ServerMessage, TcpTransport, Transport,
+};
 use libplen::physics::PhysicsState;
-use libplen::player::Player;
-
-fn send_bytes(bytes: &[u8], stream: &mut TcpStream) -> io::Result<()> {
-    let mut start = 0;
-    loop {
-        match stream.write(&bytes[start..bytes.len()]) {
-            Ok(n) => {
-                if n < bytes.len() - start {
-                    start = start + n;
-                } else {
-                    break Ok(());
-                }
-            }
-            Err(e) => match e.kind() {
-                io::ErrorKind::WouldBlock => continue,
-                io::ErrorKind::Interrupted => continue,
-                _ => return Err(e),
-            },
-        }
-    }
-}
+use libplen::player::{BuildRejection, Player};
+use libplen::udp::{LinkConditions, UdpListener};
 
-fn send_server_message(msg: &ServerMessage, stream: &mut TcpStream) -> io::Result<()> {
+fn send_server_message(msg: &ServerMessage, transport: &mut dyn Transport) -> io::Result<()> {
     let data = bincode::serialize(msg).expect("Failed to encode message");
-    let length = data.len() as u16;
-    send_bytes(&length.to_be_bytes(), stream)?;
-    send_bytes(&data, stream)
+    transport.send(msg.channel(), &data)
 }
 
 struct Client {
     id: u64,
-    message_reader: MessageReader,
+    token: u64,
+    transport: Box<dyn Transport>,
+    // The input the ship is flying with and for how many ticks it has been
     input: ClientInput,
+    input_ticks: u32,
+    // Inputs that arrived before the current one ran for its ticks
+    queued_inputs: VecDeque<ClientInput>,
+    acked_snapshot: Option<u64>,
+    // When the last message from the client arrived
+    last_seen: Instant,
+    // Since when the client has had data queued that it has not received
+    behind_since: Option<Instant>,
+}
+
+/// A client whose connection dropped. Its ship stays in the world for a
+/// while so that the client can resume the session
+struct DroppedSession {
+    id: u64,
+    token: u64,
+    since: Instant,
+}
+
+/// The connection of a kicked client, kept open until the client has
+/// received the reason
+struct ClosingConnection {
+    transport: Box<dyn Transport>,
+    since: Instant,
+    shut_down: bool,
 }
 
 struct Server {
     listener: TcpListener,
+    udp_listener: UdpListener,
     connections: Vec<Client>,
+    dropped: Vec<DroppedSession>,
+    closing: Vec<ClosingConnection>,
     state: gamestate::GameState,
+    // Sent states that may be used as delta baselines
+    snapshots: VecDeque<gamestate::GameState>,
     next_id: u64,
+    start_time: Instant,
     last_time: Instant,
+    last_heartbeat: Instant,
+    config: ServerConfig,
     p: PhysicsState,
 }
 
 impl Server {
-    pub fn new() -> Self {
-        let listener = TcpListener::bind("0.0.0.0:4444").unwrap();
+    pub fn new(config: ServerConfig) -> Self {
+        let address = config.address();
+        let listener = TcpListener::bind(&address).unwrap();
 
         listener.set_nonblocking(true).unwrap();
 
-        println!("Listening on 0.0.0.0:4444");
-
-        let rigid_body_set = RigidBodySet::new();
-        let collider_set = ColliderSet::new();
-
-        /* Create other structures necessary for the simulation. */
-        let physics_pipeline = PhysicsPipeline::new();
-        let island_manager = IslandManager::new();
-        let broad_phase = BroadPhase::new();
-        let narrow_phase = NarrowPhase::new();
-        let impulse_joint_set = ImpulseJointSet::new();
-        let multibody_joint_set = MultibodyJointSet::new();
-        let ccd_solver = CCDSolver::new();
-        let mut p = PhysicsState {
-            rigid_body_set,
-            collider_set,
-            physics_pipeline,
-            island_manager,
-            broad_phase,
-            narrow_phase,
-            impulse_joint_set,
-            multibody_joint_set,
-            ccd_solver,
-        };
+        let mut udp_listener = UdpListener::bind(&address).unwrap();
+        udp_listener.set_link_conditions(LinkConditions::from_env());
+        udp_listener.set_max_frame_size(config.max_frame_size);
+
+        println!("Listening on {} (tcp and udp)", address);
+
+        let mut p = PhysicsState::new();
 
         Self {
             listener,
+            udp_listener,
             connections: vec![],
+            dropped: vec![],
+            closing: vec![],
+            snapshots: VecDeque::new(),
             next_id: 0,
+            start_time: Instant::now(),
             last_time: Instant::now(),
-            state: gamestate::GameState::new(Some(&mut p)),
+            last_heartbeat: Instant::now(),
+            state: gamestate::GameState::new(Some(&mut p), &config.game),
+            config,
             p,
         }
     }
@@ -112,14 +124,14 @@ impl Server {
 
                 let rb = RigidBodyBuilder::dynamic().build();
 
-                let collider =
-                    ColliderBuilder::cuboid(constants::WORLD_SIZE / 2., constants::WORLD_SIZE / 2.)
-                        .mass(0.0)
-                        .translation(vector![
-                            dx * constants::WORLD_SIZE + constants::WORLD_SIZE / 2.,
-                            dy * constants::WORLD_SIZE + constants::WORLD_SIZE / 2.
-                        ])
-                        .build();
+                let world_size = self.config.game.world_size;
+                let collider = ColliderBuilder::cuboid(world_size / 2., world_size / 2.)
+                    .mass(0.0)
+                    .translation(vector![
+                        dx * world_size + world_size / 2.,
+                        dy * world_size + world_size / 2.
+                    ])
+                    .build();
 
                 let body_handle = self.p.rigid_body_set.insert(rb);
                 self.p.collider_set.insert_with_parent(
@@ -133,50 +145,25 @@ impl Server {
 
     pub fn update(&mut self) {
         let elapsed = self.last_time.elapsed();
-        let delta_time = constants::DELTA_TIME;
-        let dt_duration = std::time::Duration::from_millis(constants::SERVER_SLEEP_DURATION);
+        let delta_time = self.config.game.delta_time();
+        let dt_duration = Duration::from_secs_f32(delta_time);
         if elapsed < dt_duration {
             std::thread::sleep(dt_duration - elapsed);
         }
         self.last_time = Instant::now();
 
-        self.state.update(delta_time, &mut self.p);
-
         self.accept_new_connections();
-        self.update_clients(delta_time);
-
-        self.p.physics_pipeline.step(
-            &vector![0., 0.],
-            &IntegrationParameters::default(),
-            &mut self.p.island_manager,
-            &mut self.p.broad_phase,
-            &mut self.p.narrow_phase,
-            &mut self.p.rigid_body_set,
-            &mut self.p.collider_set,
-            &mut self.p.impulse_joint_set,
-            &mut self.p.multibody_joint_set,
-            &mut self.p.ccd_solver,
-            None,
-            &(),
-            &(),
-        );
-
-        for player in &mut self.state.players {
-            for component in &mut player.components {
-                let rb = self
-                    .p
-                    .rigid_body_set
-                    .get(component.physics_handle)
-                    .expect("Missing physics rigid body for player {player}");
+        self.receive_messages();
+        self.apply_inputs();
 
-                let pos = rb.position();
+        self.state.server_time = self.start_time.elapsed().as_secs_f64();
+        self.state
+            .update(delta_time, &mut self.p, &self.config.game);
 
-                let trans = pos.translation;
-                let rot = pos.rotation;
+        self.p.step(delta_time);
 
-                component.pos = vec2(trans.x, trans.y);
-                component.angle = rot.angle();
-            }
+        for player in &mut self.state.players {
+            player.sync_physics(&self.p.rigid_body_set);
         }
 
         for bullet in &mut self.state.bullets {
@@ -186,107 +173,332 @@ impl Server {
 
             bullet.pos = vec2(trans.x, trans.y);
             bullet.angle = pos.rotation.angle();
+            bullet.linvel = vec2(rb.linvel().x, rb.linvel().y);
         }
+
+        self.send_snapshots();
+        self.update_closing_connections();
     }
 
     fn accept_new_connections(&mut self) {
         // Read data from clients
+        let mut transports: Vec<Box<dyn Transport>> = vec![];
         for stream in self.listener.incoming() {
             match stream {
-                Ok(mut stream) => {
-                    stream.set_nonblocking(true).unwrap();
-                    println!("Got new connection {}", self.next_id);
-                    if let Err(_) =
-                        send_server_message(&ServerMessage::AssignId(self.next_id), &mut stream)
-                    {
-                        println!("Could not send assign id message");
-                        continue;
+                Ok(stream) => match TcpTransport::new(stream) {
+                    Ok(mut transport) => {
+                        transport.set_max_queued_bytes(self.config.max_queued_bytes);
+                        transport.set_max_frame_size(self.config.max_frame_size);
+                        transports.push(Box::new(transport))
                     }
-                    println!("Sent id {}", self.next_id);
-                    self.connections.push(Client {
-                        id: self.next_id,
-                        message_reader: MessageReader::new(stream),
-                        input: ClientInput::new(),
-                    });
-                    self.next_id += 1;
-                }
+                    Err(e) => println!("Could not set up tcp connection: {}", e),
+                },
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     // wait until network socket is ready, typically implemented
                     // via platform-specific APIs such as epoll or IOCP
                     break;
                 }
-                e => {
-                    e.expect("Socket listener error");
+                Err(e) => {
+                    println!("Could not accept tcp connection: {}", e);
+                    break;
                 }
             }
         }
+
+        for transport in self.udp_listener.accept() {
+            transports.push(Box::new(transport));
+        }
+
+        for transport in transports {
+            self.add_client(transport);
+        }
     }
 
-    fn update_clients(&mut self, _delta_time: f32) {
-        // Send data to clients
-        let mut clients_to_delete = vec![];
+    fn add_client(&mut self, mut transport: Box<dyn Transport>) {
+        println!("Got new connection {}", self.next_id);
+        let token = new_session_token(self.next_id);
+        let message = ServerMessage::AssignId {
+            id: self.next_id,
+            token,
+            config: self.config.game.clone(),
+        };
+        if send_server_message(&message, &mut *transport).is_err() {
+            println!("Could not send assign id message");
+            return;
+        }
+        println!("Sent id {}", self.next_id);
+        self.connections.push(Client {
+            id: self.next_id,
+            token,
+            transport,
+            input: ClientInput::new(),
+            input_ticks: 0,
+            queued_inputs: VecDeque::new(),
+            acked_snapshot: None,
+            last_seen: Instant::now(),
+            behind_since: None,
+        });
+        self.next_id += 1;
+    }
+
+    fn receive_messages(&mut self) {
+        // Clients whose connection failed, their ships wait for a reconnect
+        let mut disconnected = vec![];
+        // Clients that quit, their ships are removed right away
+        let mut left = vec![];
+        // Clients that were told to go away, they leave too
+        let mut kicked = vec![];
+        // (new connection, player id, token) of resumes of sessions whose
+        // old connection has not timed out yet
+        let mut takeovers = vec![];
         // let mut sounds_to_play = vec![];
 
-        macro_rules! remove_player_on_disconnect {
-            ($op:expr, $id:expr) => {
-                match $op {
-                    Ok(_) => {}
-                    Err(e) => match e.kind() {
-                        io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe => {
-                            println!("Player {} disconnected", $id);
-                            clients_to_delete.push($id);
-                            break;
-                        }
-                        e => panic!("Unhandled network issue: {:?}", e),
-                    },
-                };
+        macro_rules! disconnect_on_error {
+            ($op:expr, $client:expr, $clients:lifetime) => {
+                if let Err(e) = $op {
+                    if QueueFull::is(&e) {
+                        kick($client, TOO_FAR_BEHIND);
+                        kicked.push($client.id);
+                    } else {
+                        println!("Player {} disconnected: {}", $client.id, e);
+                        disconnected.push($client.id);
+                    }
+                    continue $clients;
+                }
             };
         }
 
+        let send_heartbeat =
+            self.last_heartbeat.elapsed().as_secs_f32() >= constants::HEARTBEAT_INTERVAL;
+        if send_heartbeat {
+            self.last_heartbeat = Instant::now();
+        }
+
+        let connection_timeout = Duration::from_secs_f32(self.config.connection_timeout);
+        let sessions = self
+            .connections
+            .iter()
+            .map(|client| (client.id, client.token))
+            .collect::<Vec<_>>();
         let p = &mut self.p;
-        for client in self.connections.iter_mut() {
-            remove_player_on_disconnect!(client.message_reader.fetch_bytes(), client.id);
+        'clients: for client in self.connections.iter_mut() {
+            let messages = client.transport.receive();
+            disconnect_on_error!(messages.as_ref(), client, 'clients);
+
+            if messages.as_ref().is_ok_and(|m| !m.is_empty()) {
+                client.last_seen = Instant::now();
+            }
 
-            for message in client.message_reader.iter() {
+            let silence = client.last_seen.elapsed();
+            if silence > connection_timeout {
+                println!("Player {} timed out", client.id);
+                disconnected.push(client.id);
+                continue;
+            }
+            if silence.as_secs_f32() > constants::STALE_INPUT_TIMEOUT {
+                // Don't keep thrusting on input the player may have released
+                // long ago
+                client.queued_inputs.clear();
+                client.input = ClientInput {
+                    sequence: client.input.sequence,
+                    ..ClientInput::new()
+                };
+            }
+
+            if send_heartbeat {
+                let result = send_server_message(&ServerMessage::Heartbeat, &mut *client.transport);
+                disconnect_on_error!(result, client, 'clients);
+            }
+
+            for message in messages.unwrap_or_default() {
                 match bincode::deserialize(&message) {
                     Ok(ClientMessage::Input(input)) => {
-                        client.input = input;
+                        // Late arrivals over TCP would undo newer input
+                        let newest = client.queued_inputs.back().unwrap_or(&client.input);
+                        if input.sequence > newest.sequence {
+                            client.queued_inputs.push_back(input);
+                        }
                     }
-                    Ok(ClientMessage::JoinGame { mut name }) => {
-                        if name.trim().len() != 0 {
-                            name = name.trim().unicode_truncate(20).0.to_string()
-                        } else {
-                            name = "Mr Whitespace".into();
+                    Ok(ClientMessage::JoinGame { name }) => {
+                        if !join_game(&mut self.state, p, &self.config.game, client.id, name) {
+                            kick(client, "The server is full");
+                            kicked.push(client.id);
+                            continue 'clients;
+                        }
+                    }
+                    Ok(ClientMessage::Resume { id, token, name }) => {
+                        let session = self
+                            .dropped
+                            .iter()
+                            .position(|session| session.id == id && session.token == token);
+
+                        match session {
+                            Some(index) => {
+                                self.dropped.remove(index);
+                                println!("Player {} resumed as {}", client.id, id);
+                                client.id = id;
+                                client.token = token;
+                                client.acked_snapshot = None;
+
+                                let message = ServerMessage::AssignId {
+                                    id,
+                                    token,
+                                    config: self.config.game.clone(),
+                                };
+                                let result = send_server_message(&message, &mut *client.transport);
+                                disconnect_on_error!(result, client, 'clients);
+                            }
+                            None if id != client.id && sessions.contains(&(id, token)) => {
+                                takeovers.push((client.id, id, token));
+                            }
+                            None => {
+                                let config = &self.config.game;
+                                if !join_game(&mut self.state, p, config, client.id, name) {
+                                    kick(client, "The server is full");
+                                    kicked.push(client.id);
+                                    continue 'clients;
+                                }
+                            }
                         }
-
-                        let mut player = Player::new(client.id, name, p);
-                        player.set_num_shield_points(20, p);
-                        self.state.add_player(player);
+                    }
+                    Ok(ClientMessage::Heartbeat) => {}
+                    Ok(ClientMessage::Leave) => {
+                        println!("Player {} left", client.id);
+                        left.push(client.id);
+                        break;
                     }
                     Ok(ClientMessage::AddComponent {
                         world_pos,
                         specialization,
+                        rotation,
                     }) => {
-                        for player in self.state.players.iter_mut().filter(|p| p.id == client.id) {
-                            player.add_component(
-                                specialization.clone(),
-                                p,
-                                (world_pos.x, world_pos.y),
-                            )
+                        let player = self.state.players.iter_mut().find(|p| p.id == client.id);
+                        if let Some(player) = player {
+                            let game = &self.config.game;
+                            let check = BuildRejection::check_placement(world_pos, rotation)
+                                .and_then(|()| {
+                                    player.check_build(&specialization, world_pos, game)
+                                });
+                            let rotation =
+                                math::snap_angle(rotation, constants::MODULE_ROTATION_STEP);
+                            match check {
+                                Ok(()) => {
+                                    player.add_component(
+                                        specialization.reset_state(),
+                                        p,
+                                        game,
+                                        (world_pos.x, world_pos.y),
+                                        rotation,
+                                    );
+                                    player.build_cooldown = game.build_cooldown;
+                                    player.ore -= specialization.cost();
+                                    player.stats.modules_built += 1;
+                                }
+                                Err(reason) => {
+                                    println!("Player {} could not build: {}", client.id, reason);
+                                    let rejection = ServerMessage::BuildRejected { reason };
+                                    let result =
+                                        send_server_message(&rejection, &mut *client.transport);
+                                    disconnect_on_error!(result, client, 'clients);
+                                }
+                            }
+                        }
+                    }
+                    Ok(ClientMessage::SwitchTeam { team }) => {
+                        let config = &self.config.game;
+                        match self.state.switch_team(client.id, team, p, config) {
+                            Ok(()) => println!("Player {} switched to team {}", client.id, team),
+                            Err(reason) => {
+                                println!("Player {} could not switch teams: {}", client.id, reason)
+                            }
                         }
                     }
+                    Ok(ClientMessage::AckSnapshot(tick)) => {
+                        client.acked_snapshot = client.acked_snapshot.max(Some(tick));
+                    }
+                    Ok(ClientMessage::Ping(client_time)) => {
+                        let pong = ServerMessage::Pong {
+                            client_time,
+                            server_time: self.start_time.elapsed().as_secs_f64(),
+                        };
+                        let result = send_server_message(&pong, &mut *client.transport);
+                        disconnect_on_error!(result, client, 'clients);
+                    }
                     Err(_) => {
                         println!("Could not decode message from {}, deleting", client.id);
-                        clients_to_delete.push(client.id);
+                        disconnected.push(client.id);
+                        break;
                     }
                 }
             }
+        }
+
+        for (from, id, token) in takeovers {
+            let gone = [&disconnected, &left, &kicked];
+            if gone.iter().any(|ids| ids.contains(&from)) {
+                continue;
+            }
+            // Whatever happened to the old connection, the player stays
+            for ids in [&mut disconnected, &mut left, &mut kicked] {
+                ids.retain(|gone| *gone != id);
+            }
+            self.take_over_session(from, id, token);
+        }
+        self.remove_clients(disconnected, left, kicked);
+    }
+
+    /// The client reconnected before the server noticed that its old
+    /// connection is dead. The old connection is closed and the new one
+    /// takes over the player
+    fn take_over_session(&mut self, from: u64, id: u64, token: u64) {
+        let old = self
+            .connections
+            .iter()
+            .position(|client| client.id == id && client.token == token);
+        if let Some(old) = old {
+            self.connections.remove(old);
+        }
+
+        let Some(client) = self.connections.iter_mut().find(|client| client.id == from) else {
+            return;
+        };
+        println!("Player {} took over the session of {}", from, id);
+        client.id = id;
+        client.token = token;
+        client.acked_snapshot = None;
+
+        let message = ServerMessage::AssignId {
+            id,
+            token,
+            config: self.config.game.clone(),
+        };
+        // A failed send shows up as a dropped connection on the next receive
+        if let Err(e) = send_server_message(&message, &mut *client.transport) {
+            println!("Could not resume player {}: {}", id, e);
+        }
+    }
 
-            let result = send_server_message(
-                &ServerMessage::GameState(self.state.clone()),
-                &mut client.message_reader.stream,
-            );
-            remove_player_on_disconnect!(result, client.id);
+    /// Picks the input each ship flies with this tick. Every input runs for
+    /// as many ticks as the client predicted it for, so that replaying it
+    /// on the client ends up where the server did
+    fn apply_inputs(&mut self) {
+        for client in &mut self.connections {
+            // Skip ahead rather than fall further and further behind the
+            // client when inputs pile up
+            while client.queued_inputs.len() > constants::MAX_QUEUED_INPUTS {
+                client.queued_inputs.pop_front();
+            }
+            while client.input_ticks >= client.input.ticks {
+                match client.queued_inputs.pop_front() {
+                    Some(input) => {
+                        client.input = input;
+                        client.input_ticks = 0;
+                    }
+                    // Keep flying with the last input until the next arrives
+                    None => break,
+                }
+            }
+            client.input_ticks += 1;
 
             for player in &mut self.state.players {
                 if player.id == client.id {
@@ -294,17 +506,194 @@ impl Server {
                 }
             }
         }
+    }
+
+    fn send_snapshots(&mut self) {
+        let mut disconnected = vec![];
+        let mut kicked = vec![];
+        let delta_time = self.config.game.delta_time();
+
+        self.take_snapshot();
+        for client in self.connections.iter_mut() {
+            let baseline = client
+                .acked_snapshot
+                .and_then(|acked| self.snapshots.iter().find(|old| old.tick == acked));
+
+            let last_input = client.input.sequence;
+            let input_ticks = client.input_ticks;
+            let message = match baseline {
+                Some(old) => ServerMessage::GameStateDelta {
+                    delta: GameStateDelta::between(old, &self.state, delta_time),
+                    last_input,
+                    input_ticks,
+                },
+                None => ServerMessage::GameState {
+                    state: self.state.clone(),
+                    last_input,
+                    input_ticks,
+                },
+            };
+
+            if let Err(e) = send_server_message(&message, &mut *client.transport) {
+                if QueueFull::is(&e) {
+                    kick(client, TOO_FAR_BEHIND);
+                    kicked.push(client.id);
+                } else {
+                    println!("Player {} disconnected: {}", client.id, e);
+                    disconnected.push(client.id);
+                }
+                continue;
+            }
+
+            if client.transport.backlog() == 0 {
+                client.behind_since = None;
+            } else {
+                let behind_since = *client.behind_since.get_or_insert_with(Instant::now);
+                if behind_since.elapsed().as_secs_f32() > constants::SLOW_CLIENT_TIMEOUT {
+                    kick(client, TOO_FAR_BEHIND);
+                    kicked.push(client.id);
+                }
+            }
+        }
+
+        self.remove_clients(disconnected, vec![], kicked);
+    }
+
+    /// Drops the connections of clients that are gone. Disconnected clients
+    /// keep their ship for a while, the others take it with them
+    fn remove_clients(&mut self, disconnected: Vec<u64>, mut left: Vec<u64>, kicked: Vec<u64>) {
+        for client in &self.connections {
+            if !disconnected.contains(&client.id) {
+                continue;
+            }
+            // The ship drifts without input until the player is back
+            for player in self.state.players.iter_mut().filter(|p| p.id == client.id) {
+                player.set_input(&ClientInput::new());
+                self.dropped.push(DroppedSession {
+                    id: client.id,
+                    token: client.token,
+                    since: Instant::now(),
+                });
+            }
+        }
+
+        left.extend_from_slice(&kicked);
+
+        let grace_period = std::time::Duration::from_secs_f32(constants::RECONNECT_GRACE_PERIOD);
+        for session in &self.dropped {
+            if session.since.elapsed() > grace_period {
+                println!("Session of player {} expired", session.id);
+                left.push(session.id);
+            }
+        }
 
         self.state
-            .players
-            .retain(|player| !clients_to_delete.contains(&player.id));
-        self.connections
-            .retain(|client| !clients_to_delete.contains(&client.id));
+            .remove_players(&left, &mut self.p, &self.config.game);
+        self.dropped.retain(|session| !left.contains(&session.id));
+
+        for client in std::mem::take(&mut self.connections) {
+            if kicked.contains(&client.id) {
+                self.closing.push(ClosingConnection {
+                    transport: client.transport,
+                    since: Instant::now(),
+                    shut_down: false,
+                });
+            } else if !disconnected.contains(&client.id) && !left.contains(&client.id) {
+                self.connections.push(client);
+            }
+        }
+    }
+
+    /// Delivers what is left to kicked clients, then closes their
+    /// connections once they hang up or take too long
+    fn update_closing_connections(&mut self) {
+        let linger_time = Duration::from_secs_f32(constants::KICK_LINGER_TIME);
+        self.closing.retain_mut(|closing| {
+            // Flushes the queue and reads until the peer hangs up. Whatever
+            // the client still says is ignored
+            if closing.since.elapsed() > linger_time || closing.transport.receive().is_err() {
+                return false;
+            }
+            if !closing.shut_down && closing.transport.backlog() == 0 {
+                closing.shut_down = true;
+                return closing.transport.shutdown().is_ok();
+            }
+            true
+        });
     }
+
+    /// Remembers the current state as a possible delta baseline
+    fn take_snapshot(&mut self) {
+        self.snapshots.push_back(self.state.clone());
+        if self.snapshots.len() > constants::SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+/// Tells the client why it is being dropped. The reason skips ahead of any
+/// states queued for a slow client
+const TOO_FAR_BEHIND: &str = "Connection too slow to keep up with the game";
+
+fn kick(client: &mut Client, reason: &str) {
+    println!("Kicking player {}: {}", client.id, reason);
+    let message = ServerMessage::Kicked {
+        reason: reason.into(),
+    };
+    client.transport.discard_unreliable();
+    // The connection is dropped either way
+    let _ = send_server_message(&message, &mut *client.transport);
+}
+
+/// Hard to guess token that proves a client owns a session
+fn new_session_token(id: u64) -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(id);
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+/// Adds a ship for the player unless it has one already. Returns false if
+/// the game is full
+fn join_game(
+    state: &mut gamestate::GameState,
+    p: &mut PhysicsState,
+    config: &GameConfig,
+    id: u64,
+    mut name: String,
+) -> bool {
+    if state.get_player_by_id(id).is_some() {
+        return true;
+    }
+    if state.players.len() >= config.max_players {
+        return false;
+    }
+
+    if !name.trim().is_empty() {
+        name = name.trim().unicode_truncate(20).0.to_string()
+    } else {
+        name = "Mr Whitespace".into();
+    }
+
+    state.add_player(Player::new(id, name), p, config);
+    true
 }
 
 fn main() {
-    let mut server = Server::new();
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut server = Server::new(config);
     //server.init_walls();
     loop {
         server.update();